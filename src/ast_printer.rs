@@ -0,0 +1,348 @@
+use crate::expr::{
+    BinaryOp, Expr, ExprVisitor, FunDecl, Literal, LogicalOp, SourceLocation, Stmt, StmtVisitor,
+    Symbol, UnaryOp,
+};
+use crate::tree_interpreter::Value;
+
+/// Writes `stmt` back out as Lox source on a single line. Parentheses are
+/// kept where the source had them, so the text parses to the same tree,
+/// except that a `for` loop's initializer comes out in a block around it.
+pub fn print_stmt(stmt: &Stmt) -> String {
+    stmt.accept(&mut AstPrinter)
+}
+
+/// Like `print_stmt`, for an expression.
+pub fn print_expr(expr: &Expr) -> String {
+    expr.accept(&mut AstPrinter)
+}
+
+struct AstPrinter;
+
+impl AstPrinter {
+    fn list(&mut self, exprs: &[Expr]) -> String {
+        let printed: Vec<_> = exprs.iter().map(|expr| expr.accept(self)).collect();
+        printed.join(", ")
+    }
+
+    fn names(names: &[Symbol]) -> String {
+        let names: Vec<_> = names.iter().map(|name| name.name.as_str()).collect();
+        names.join(", ")
+    }
+
+    fn label(label: Option<&Symbol>) -> String {
+        label.map_or(String::new(), |label| format!("{}: ", label.name))
+    }
+
+    fn jump(keyword: &str, label: Option<&Symbol>) -> String {
+        match label {
+            Some(label) => format!("{} {};", keyword, label.name),
+            None => format!("{};", keyword),
+        }
+    }
+}
+
+impl ExprVisitor for AstPrinter {
+    type Output = String;
+
+    fn visit_assign(&mut self, name: &Symbol, value: &Expr) -> Self::Output {
+        format!("{} = {}", name.name, value.accept(self))
+    }
+
+    fn visit_assign_destructure(
+        &mut self,
+        _loc: &SourceLocation,
+        names: &[Symbol],
+        value: &Expr,
+    ) -> Self::Output {
+        format!("({}) = {}", Self::names(names), value.accept(self))
+    }
+
+    fn visit_unary(&mut self, op: &UnaryOp, operand: &Expr) -> Self::Output {
+        format!("{}{}", op.op_type.symbol(), operand.accept(self))
+    }
+
+    fn visit_binary(&mut self, lhs: &Expr, op: &BinaryOp, rhs: &Expr) -> Self::Output {
+        format!(
+            "{} {} {}",
+            lhs.accept(self),
+            op.op_type.symbol(),
+            rhs.accept(self)
+        )
+    }
+
+    fn visit_call(&mut self, callee: &Expr, _loc: &SourceLocation, args: &[Expr]) -> Self::Output {
+        format!("{}({})", callee.accept(self), self.list(args))
+    }
+
+    fn visit_get(&mut self, object: &Expr, name: &Symbol, nil_safe: bool) -> Self::Output {
+        let dot = if nil_safe { "?." } else { "." };
+        format!("{}{}{}", object.accept(self), dot, name.name)
+    }
+
+    fn visit_index(&mut self, object: &Expr, _loc: &SourceLocation, index: &Expr) -> Self::Output {
+        format!("{}[{}]", object.accept(self), index.accept(self))
+    }
+
+    fn visit_set_index(
+        &mut self,
+        object: &Expr,
+        _loc: &SourceLocation,
+        index: &Expr,
+        value: &Expr,
+    ) -> Self::Output {
+        format!(
+            "{}[{}] = {}",
+            object.accept(self),
+            index.accept(self),
+            value.accept(self)
+        )
+    }
+
+    fn visit_list(&mut self, elements: &[Expr]) -> Self::Output {
+        format!("[{}]", self.list(elements))
+    }
+
+    fn visit_comprehension(
+        &mut self,
+        element: &Expr,
+        _loc: &SourceLocation,
+        name: &Symbol,
+        iterable: &Expr,
+    ) -> Self::Output {
+        format!(
+            "[{} for (var {} in {})]",
+            element.accept(self),
+            name.name,
+            iterable.accept(self)
+        )
+    }
+
+    fn visit_grouping(&mut self, _loc: &SourceLocation, expr: &Expr) -> Self::Output {
+        format!("({})", expr.accept(self))
+    }
+
+    fn visit_literal(&mut self, literal: &Literal) -> Self::Output {
+        match literal {
+            Literal::String(s) => format!("\"{}\"", s),
+            Literal::Number(_, Some(lexeme)) => lexeme.clone(),
+            Literal::Number(n, None) => Value::Number(*n).to_string(),
+            Literal::True => "true".to_string(),
+            Literal::False => "false".to_string(),
+            Literal::Nil => "nil".to_string(),
+        }
+    }
+
+    fn visit_logical(&mut self, lhs: &Expr, op: &LogicalOp, rhs: &Expr) -> Self::Output {
+        let op = match op {
+            LogicalOp::And => "and",
+            LogicalOp::Or => "or",
+        };
+        format!("{} {} {}", lhs.accept(self), op, rhs.accept(self))
+    }
+
+    fn visit_set(&mut self, object: &Expr, name: &Symbol, value: &Expr) -> Self::Output {
+        format!(
+            "{}.{} = {}",
+            object.accept(self),
+            name.name,
+            value.accept(self)
+        )
+    }
+
+    fn visit_super(&mut self, _loc: &SourceLocation, method: &Symbol) -> Self::Output {
+        format!("super.{}", method.name)
+    }
+
+    fn visit_this(&mut self, _loc: &SourceLocation) -> Self::Output {
+        "this".to_string()
+    }
+
+    fn visit_variable(&mut self, name: &Symbol) -> Self::Output {
+        name.name.clone()
+    }
+}
+
+impl StmtVisitor for AstPrinter {
+    type Output = String;
+
+    fn visit_expr_stmt(&mut self, expr: &Expr) -> Self::Output {
+        format!("{};", expr.accept(self))
+    }
+
+    fn visit_fun_decl(&mut self, decl: &FunDecl) -> Self::Output {
+        let mut params: Vec<_> = decl.params.iter().map(|p| p.name.clone()).collect();
+        if let Some(rest) = &decl.rest {
+            params.push(format!("...{}", rest.name));
+        }
+        format!(
+            "fun {}({}) {}",
+            decl.name.name,
+            params.join(", "),
+            self.visit_block(&decl.body)
+        )
+    }
+
+    fn visit_if(
+        &mut self,
+        condition: &Expr,
+        then_branch: &Stmt,
+        else_branch: Option<&Stmt>,
+    ) -> Self::Output {
+        let mut printed = format!(
+            "if ({}) {}",
+            condition.accept(self),
+            then_branch.accept(self)
+        );
+        if let Some(else_branch) = else_branch {
+            printed.push_str(" else ");
+            printed.push_str(&else_branch.accept(self));
+        }
+        printed
+    }
+
+    fn visit_print(&mut self, expr: &Expr) -> Self::Output {
+        format!("print {};", expr.accept(self))
+    }
+
+    fn visit_eprint(&mut self, expr: &Expr) -> Self::Output {
+        format!("eprint {};", expr.accept(self))
+    }
+
+    fn visit_debug(&mut self, expr: &Expr) -> Self::Output {
+        format!("debug {};", expr.accept(self))
+    }
+
+    fn visit_assert(
+        &mut self,
+        _loc: &SourceLocation,
+        condition: &Expr,
+        message: Option<&Expr>,
+    ) -> Self::Output {
+        match message {
+            Some(message) => format!(
+                "assert {}, {};",
+                condition.accept(self),
+                message.accept(self)
+            ),
+            None => format!("assert {};", condition.accept(self)),
+        }
+    }
+
+    fn visit_var_decl(&mut self, name: &Symbol, initializer: Option<&Expr>) -> Self::Output {
+        match initializer {
+            Some(initializer) => format!("var {} = {};", name.name, initializer.accept(self)),
+            None => format!("var {};", name.name),
+        }
+    }
+
+    fn visit_var_destructure(
+        &mut self,
+        _loc: &SourceLocation,
+        names: &[Symbol],
+        initializer: &Expr,
+    ) -> Self::Output {
+        format!(
+            "var ({}) = {};",
+            Self::names(names),
+            initializer.accept(self)
+        )
+    }
+
+    fn visit_const_decl(&mut self, name: &Symbol, initializer: &Expr) -> Self::Output {
+        format!("const {} = {};", name.name, initializer.accept(self))
+    }
+
+    fn visit_block(&mut self, stmts: &[Stmt]) -> Self::Output {
+        if stmts.is_empty() {
+            return "{}".to_string();
+        }
+        let printed: Vec<_> = stmts.iter().map(|stmt| stmt.accept(self)).collect();
+        format!("{{ {} }}", printed.join(" "))
+    }
+
+    fn visit_empty(&mut self) -> Self::Output {
+        ";".to_string()
+    }
+
+    fn visit_repeat(
+        &mut self,
+        _loc: &SourceLocation,
+        label: Option<&Symbol>,
+        count: &Expr,
+        body: &Stmt,
+    ) -> Self::Output {
+        format!(
+            "{}repeat ({}) {}",
+            Self::label(label),
+            count.accept(self),
+            body.accept(self)
+        )
+    }
+
+    fn visit_while(
+        &mut self,
+        label: Option<&Symbol>,
+        condition: &Expr,
+        body: &Stmt,
+        increment: Option<&Expr>,
+    ) -> Self::Output {
+        let condition = condition.accept(self);
+        let body = body.accept(self);
+        match increment {
+            Some(increment) => format!(
+                "{}for (; {}; {}) {}",
+                Self::label(label),
+                condition,
+                increment.accept(self),
+                body
+            ),
+            None => format!("{}while ({}) {}", Self::label(label), condition, body),
+        }
+    }
+
+    fn visit_for_in(
+        &mut self,
+        _loc: &SourceLocation,
+        label: Option<&Symbol>,
+        name: &Symbol,
+        iterable: &Expr,
+        body: &Stmt,
+    ) -> Self::Output {
+        format!(
+            "{}for (var {} in {}) {}",
+            Self::label(label),
+            name.name,
+            iterable.accept(self),
+            body.accept(self)
+        )
+    }
+
+    fn visit_do_while(
+        &mut self,
+        label: Option<&Symbol>,
+        body: &Stmt,
+        condition: &Expr,
+    ) -> Self::Output {
+        format!(
+            "{}do {} while ({});",
+            Self::label(label),
+            body.accept(self),
+            condition.accept(self)
+        )
+    }
+
+    fn visit_break(&mut self, label: Option<&Symbol>) -> Self::Output {
+        Self::jump("break", label)
+    }
+
+    fn visit_continue(&mut self, label: Option<&Symbol>) -> Self::Output {
+        Self::jump("continue", label)
+    }
+
+    fn visit_return(&mut self, _loc: &SourceLocation, value: Option<&Expr>) -> Self::Output {
+        match value {
+            Some(value) => format!("return {};", value.accept(self)),
+            None => "return;".to_string(),
+        }
+    }
+}
@@ -1,7 +1,136 @@
+use std::cell::{Cell, RefCell};
+
+/// Which stage of the pipeline reported an error.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorKind {
+    Scan,
+    Parse,
+    Resolve,
+    Runtime,
+}
+
+impl ErrorKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorKind::Scan => "scan",
+            ErrorKind::Parse => "parse",
+            ErrorKind::Resolve => "resolve",
+            ErrorKind::Runtime => "runtime",
+        }
+    }
+}
+
+/// An error from any stage, ready to be passed to `format_error`.
+#[derive(Clone, Debug)]
+pub struct Diagnostic {
+    pub kind: ErrorKind,
+    pub message: String,
+    pub line: usize,
+    pub col: i64,
+}
+
+thread_local! {
+    static JSON_ERRORS: Cell<bool> = const { Cell::new(false) };
+    static PENDING: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Switches error reporting to JSON. Errors are then collected and written
+/// as a single array by `flush_errors` instead of printed one by one.
+pub fn set_json_errors(enabled: bool) {
+    JSON_ERRORS.set(enabled);
+}
+
+pub fn json_errors() -> bool {
+    JSON_ERRORS.get()
+}
+
 pub fn formatter(message: &str, line: usize, col: i64) {
     eprintln!("Error {} at line {} col: {}", message, line, col);
 }
 
-pub fn format_error(message: &str, line: usize, col: i64) {
-    formatter(message, line, col);
+pub fn format_error(kind: ErrorKind, message: &str, line: usize, col: i64) {
+    if json_errors() {
+        let entry = format!(
+            "{{ \"line\": {}, \"col\": {}, \"message\": \"{}\", \"kind\": \"{}\" }}",
+            line,
+            col,
+            escape_json(message),
+            kind.as_str()
+        );
+        PENDING.with_borrow_mut(|pending| pending.push(entry));
+    } else {
+        formatter(message, line, col);
+    }
+}
+
+/// Reports something suspicious that doesn't stop the program. In JSON
+/// mode it is collected alongside the errors with the kind `warning`.
+pub fn format_warning(message: &str, line: usize, col: i64) {
+    if json_errors() {
+        let entry = format!(
+            "{{ \"line\": {}, \"col\": {}, \"message\": \"{}\", \"kind\": \"warning\" }}",
+            line,
+            col,
+            escape_json(message)
+        );
+        PENDING.with_borrow_mut(|pending| pending.push(entry));
+    } else {
+        eprintln!("Warning {} at line {} col: {}", message, line, col);
+    }
+}
+
+/// Writes the errors collected in JSON mode to stderr as one array, which
+/// is `[]` when nothing went wrong.
+pub fn flush_errors() {
+    if json_errors() {
+        eprintln!("{}", take_pending());
+    }
+}
+
+/// The collected JSON entries as an array, emptying the collection.
+pub(crate) fn take_pending() -> String {
+    let entries = PENDING.with_borrow_mut(std::mem::take);
+    format!("[{}]", entries.join(", "))
+}
+
+pub fn escape_json(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_mode_collects_every_error_into_one_array() {
+        set_json_errors(true);
+        let errors = crate::parse("var = 1;\nprint (;\n".to_string()).unwrap_err();
+        assert_eq!(errors.len(), 2);
+        for err in &errors {
+            format_error(err.kind, &err.message, err.line, err.col);
+        }
+        assert_eq!(
+            take_pending(),
+            "[{ \"line\": 1, \"col\": 5, \"message\": \"Expect a variable name, found '='\", \"kind\": \"parse\" }, \
+             { \"line\": 2, \"col\": 8, \"message\": \"Expected expression, found ';'\", \"kind\": \"parse\" }]"
+        );
+        assert_eq!(take_pending(), "[]");
+    }
+
+    #[test]
+    fn json_strings_are_escaped() {
+        assert_eq!(escape_json("say \"hi\"\n\\"), "say \\\"hi\\\"\\n\\\\");
+    }
 }
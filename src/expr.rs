@@ -1,11 +1,25 @@
 #[derive(Debug, Clone)]
 pub enum Expr {
     Assign(Symbol, Box<Expr>),
+    /// `(a, b) = list`; the location is the opening parenthesis.
+    AssignDestructure(SourceLocation, Vec<Symbol>, Box<Expr>),
     Unary(UnaryOp, Box<Expr>),
     Binary(Box<Expr>, BinaryOp, Box<Expr>),
     Call(Box<Expr>, SourceLocation, Vec<Expr>),
-    Get(Box<Expr>, Symbol),
-    Grouping(Box<Expr>),
+    /// `object.name`, or `object?.name` when the flag is set, which is nil
+    /// for a nil object instead of an error.
+    Get(Box<Expr>, Symbol, bool),
+    /// `object[index]`; the location is the opening bracket.
+    Index(Box<Expr>, SourceLocation, Box<Expr>),
+    /// `object[index] = value`.
+    SetIndex(Box<Expr>, SourceLocation, Box<Expr>, Box<Expr>),
+    /// A list literal such as `[1, 2, 3]`.
+    List(Vec<Expr>),
+    /// `[element for (var name in iterable)]`; the location is the `for`
+    /// keyword.
+    Comprehension(Box<Expr>, SourceLocation, Symbol, Box<Expr>),
+    /// The location is the opening parenthesis.
+    Grouping(SourceLocation, Box<Expr>),
     Literal(Literal),
     Logical(Box<Expr>, LogicalOp, Box<Expr>),
     Set(Box<Expr>, Symbol, Box<Expr>),
@@ -17,14 +31,285 @@ pub enum Expr {
 #[derive(Debug, Clone)]
 pub enum Stmt {
     Expr(Expr),
+    FunDecl(FunDecl),
+    If(Expr, Box<Stmt>, Option<Box<Stmt>>),
     Print(Expr),
+    /// Like `print`, but to the interpreter's error output.
+    Eprint(Expr),
+    Debug(Expr),
+    /// `assert condition;` or `assert condition, message;`, failing the
+    /// program when the condition is falsy. The location is the `assert`
+    /// keyword.
+    Assert(SourceLocation, Expr, Option<Expr>),
     VarDecl(Symbol, Option<Expr>),
+    /// `var (a, b) = list;`, binding each name to the element in the
+    /// same position. The location is the opening parenthesis.
+    VarDestructure(SourceLocation, Vec<Symbol>, Expr),
+    ConstDecl(Symbol, Expr),
+    Block(Vec<Stmt>),
+    /// A lone `;`, which does nothing.
+    Empty,
+    /// `repeat (count) body`; the location is the `repeat` keyword.
+    /// Loops carry their optional label, as in `outer: while (...)`.
+    Repeat(SourceLocation, Option<Symbol>, Expr, Box<Stmt>),
+    /// `while (condition) body`. The increment is the last clause of a
+    /// desugared `for`; it runs after every iteration, even one that ended
+    /// in `continue`.
+    While(Option<Symbol>, Expr, Box<Stmt>, Option<Expr>),
+    /// `for (var name in iterable) body`; the location is the `for`
+    /// keyword.
+    ForIn(SourceLocation, Option<Symbol>, Symbol, Expr, Box<Stmt>),
+    /// `do body while (condition);`, which always runs the body once.
+    DoWhile(Option<Symbol>, Box<Stmt>, Expr),
+    /// `break` and `continue`, with the label of the loop they target.
+    Break(Option<Symbol>),
+    Continue(Option<Symbol>),
+    Return(SourceLocation, Option<Expr>),
+}
+
+impl Expr {
+    /// The variant name, as shown by `--profile`.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Expr::Assign(..) => "Assign",
+            Expr::AssignDestructure(..) => "AssignDestructure",
+            Expr::Unary(..) => "Unary",
+            Expr::Binary(..) => "Binary",
+            Expr::Call(..) => "Call",
+            Expr::Get(..) => "Get",
+            Expr::Index(..) => "Index",
+            Expr::SetIndex(..) => "SetIndex",
+            Expr::List(..) => "List",
+            Expr::Comprehension(..) => "Comprehension",
+            Expr::Grouping(..) => "Grouping",
+            Expr::Literal(..) => "Literal",
+            Expr::Logical(..) => "Logical",
+            Expr::Set(..) => "Set",
+            Expr::Super(..) => "Super",
+            Expr::This(..) => "This",
+            Expr::Variable(..) => "Variable",
+        }
+    }
+}
+
+impl Stmt {
+    /// The variant name, as shown by `--profile`.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Stmt::Expr(..) => "ExprStmt",
+            Stmt::FunDecl(..) => "FunDecl",
+            Stmt::If(..) => "If",
+            Stmt::Print(..) => "Print",
+            Stmt::Eprint(..) => "Eprint",
+            Stmt::Debug(..) => "Debug",
+            Stmt::Assert(..) => "Assert",
+            Stmt::VarDecl(..) => "VarDecl",
+            Stmt::VarDestructure(..) => "VarDestructure",
+            Stmt::ConstDecl(..) => "ConstDecl",
+            Stmt::Block(..) => "Block",
+            Stmt::Empty => "Empty",
+            Stmt::Repeat(..) => "Repeat",
+            Stmt::While(..) => "While",
+            Stmt::ForIn(..) => "ForIn",
+            Stmt::DoWhile(..) => "DoWhile",
+            Stmt::Break(..) => "Break",
+            Stmt::Continue(..) => "Continue",
+            Stmt::Return(..) => "Return",
+        }
+    }
+}
+
+/// One method per `Expr` variant, so a new variant can't be forgotten by
+/// a pass over the tree. `Expr::accept` picks the method to call.
+pub trait ExprVisitor {
+    type Output;
+
+    fn visit_assign(&mut self, name: &Symbol, value: &Expr) -> Self::Output;
+    fn visit_assign_destructure(
+        &mut self,
+        loc: &SourceLocation,
+        names: &[Symbol],
+        value: &Expr,
+    ) -> Self::Output;
+    fn visit_unary(&mut self, op: &UnaryOp, operand: &Expr) -> Self::Output;
+    fn visit_binary(&mut self, lhs: &Expr, op: &BinaryOp, rhs: &Expr) -> Self::Output;
+    fn visit_call(&mut self, callee: &Expr, loc: &SourceLocation, args: &[Expr]) -> Self::Output;
+    fn visit_get(&mut self, object: &Expr, name: &Symbol, nil_safe: bool) -> Self::Output;
+    fn visit_index(&mut self, object: &Expr, loc: &SourceLocation, index: &Expr) -> Self::Output;
+    fn visit_set_index(
+        &mut self,
+        object: &Expr,
+        loc: &SourceLocation,
+        index: &Expr,
+        value: &Expr,
+    ) -> Self::Output;
+    fn visit_list(&mut self, elements: &[Expr]) -> Self::Output;
+    fn visit_comprehension(
+        &mut self,
+        element: &Expr,
+        loc: &SourceLocation,
+        name: &Symbol,
+        iterable: &Expr,
+    ) -> Self::Output;
+    fn visit_grouping(&mut self, loc: &SourceLocation, expr: &Expr) -> Self::Output;
+    fn visit_literal(&mut self, literal: &Literal) -> Self::Output;
+    fn visit_logical(&mut self, lhs: &Expr, op: &LogicalOp, rhs: &Expr) -> Self::Output;
+    fn visit_set(&mut self, object: &Expr, name: &Symbol, value: &Expr) -> Self::Output;
+    fn visit_super(&mut self, loc: &SourceLocation, method: &Symbol) -> Self::Output;
+    fn visit_this(&mut self, loc: &SourceLocation) -> Self::Output;
+    fn visit_variable(&mut self, name: &Symbol) -> Self::Output;
+}
+
+/// The `Stmt` counterpart of `ExprVisitor`.
+pub trait StmtVisitor {
+    type Output;
+
+    fn visit_expr_stmt(&mut self, expr: &Expr) -> Self::Output;
+    fn visit_fun_decl(&mut self, decl: &FunDecl) -> Self::Output;
+    fn visit_if(
+        &mut self,
+        condition: &Expr,
+        then_branch: &Stmt,
+        else_branch: Option<&Stmt>,
+    ) -> Self::Output;
+    fn visit_print(&mut self, expr: &Expr) -> Self::Output;
+    fn visit_eprint(&mut self, expr: &Expr) -> Self::Output;
+    fn visit_debug(&mut self, expr: &Expr) -> Self::Output;
+    fn visit_assert(
+        &mut self,
+        loc: &SourceLocation,
+        condition: &Expr,
+        message: Option<&Expr>,
+    ) -> Self::Output;
+    fn visit_var_decl(&mut self, name: &Symbol, initializer: Option<&Expr>) -> Self::Output;
+    fn visit_var_destructure(
+        &mut self,
+        loc: &SourceLocation,
+        names: &[Symbol],
+        initializer: &Expr,
+    ) -> Self::Output;
+    fn visit_const_decl(&mut self, name: &Symbol, initializer: &Expr) -> Self::Output;
+    fn visit_block(&mut self, stmts: &[Stmt]) -> Self::Output;
+    fn visit_empty(&mut self) -> Self::Output;
+    fn visit_repeat(
+        &mut self,
+        loc: &SourceLocation,
+        label: Option<&Symbol>,
+        count: &Expr,
+        body: &Stmt,
+    ) -> Self::Output;
+    fn visit_while(
+        &mut self,
+        label: Option<&Symbol>,
+        condition: &Expr,
+        body: &Stmt,
+        increment: Option<&Expr>,
+    ) -> Self::Output;
+    fn visit_for_in(
+        &mut self,
+        loc: &SourceLocation,
+        label: Option<&Symbol>,
+        name: &Symbol,
+        iterable: &Expr,
+        body: &Stmt,
+    ) -> Self::Output;
+    fn visit_do_while(
+        &mut self,
+        label: Option<&Symbol>,
+        body: &Stmt,
+        condition: &Expr,
+    ) -> Self::Output;
+    fn visit_break(&mut self, label: Option<&Symbol>) -> Self::Output;
+    fn visit_continue(&mut self, label: Option<&Symbol>) -> Self::Output;
+    fn visit_return(&mut self, loc: &SourceLocation, value: Option<&Expr>) -> Self::Output;
+}
+
+impl Expr {
+    pub fn accept<V: ExprVisitor>(&self, visitor: &mut V) -> V::Output {
+        match self {
+            Expr::Assign(name, value) => visitor.visit_assign(name, value),
+            Expr::AssignDestructure(loc, names, value) => {
+                visitor.visit_assign_destructure(loc, names, value)
+            }
+            Expr::Unary(op, operand) => visitor.visit_unary(op, operand),
+            Expr::Binary(lhs, op, rhs) => visitor.visit_binary(lhs, op, rhs),
+            Expr::Call(callee, loc, args) => visitor.visit_call(callee, loc, args),
+            Expr::Get(object, name, nil_safe) => visitor.visit_get(object, name, *nil_safe),
+            Expr::Index(object, loc, index) => visitor.visit_index(object, loc, index),
+            Expr::SetIndex(object, loc, index, value) => {
+                visitor.visit_set_index(object, loc, index, value)
+            }
+            Expr::List(elements) => visitor.visit_list(elements),
+            Expr::Comprehension(element, loc, name, iterable) => {
+                visitor.visit_comprehension(element, loc, name, iterable)
+            }
+            Expr::Grouping(loc, expr) => visitor.visit_grouping(loc, expr),
+            Expr::Literal(literal) => visitor.visit_literal(literal),
+            Expr::Logical(lhs, op, rhs) => visitor.visit_logical(lhs, op, rhs),
+            Expr::Set(object, name, value) => visitor.visit_set(object, name, value),
+            Expr::Super(loc, method) => visitor.visit_super(loc, method),
+            Expr::This(loc) => visitor.visit_this(loc),
+            Expr::Variable(name) => visitor.visit_variable(name),
+        }
+    }
+}
+
+impl Stmt {
+    pub fn accept<V: StmtVisitor>(&self, visitor: &mut V) -> V::Output {
+        match self {
+            Stmt::Expr(expr) => visitor.visit_expr_stmt(expr),
+            Stmt::FunDecl(decl) => visitor.visit_fun_decl(decl),
+            Stmt::If(condition, then_branch, else_branch) => {
+                visitor.visit_if(condition, then_branch, else_branch.as_deref())
+            }
+            Stmt::Print(expr) => visitor.visit_print(expr),
+            Stmt::Eprint(expr) => visitor.visit_eprint(expr),
+            Stmt::Debug(expr) => visitor.visit_debug(expr),
+            Stmt::Assert(loc, condition, message) => {
+                visitor.visit_assert(loc, condition, message.as_ref())
+            }
+            Stmt::VarDecl(name, initializer) => visitor.visit_var_decl(name, initializer.as_ref()),
+            Stmt::VarDestructure(loc, names, initializer) => {
+                visitor.visit_var_destructure(loc, names, initializer)
+            }
+            Stmt::ConstDecl(name, initializer) => visitor.visit_const_decl(name, initializer),
+            Stmt::Block(stmts) => visitor.visit_block(stmts),
+            Stmt::Empty => visitor.visit_empty(),
+            Stmt::Repeat(loc, label, count, body) => {
+                visitor.visit_repeat(loc, label.as_ref(), count, body)
+            }
+            Stmt::While(label, condition, body, increment) => {
+                visitor.visit_while(label.as_ref(), condition, body, increment.as_ref())
+            }
+            Stmt::ForIn(loc, label, name, iterable, body) => {
+                visitor.visit_for_in(loc, label.as_ref(), name, iterable, body)
+            }
+            Stmt::DoWhile(label, body, condition) => {
+                visitor.visit_do_while(label.as_ref(), body, condition)
+            }
+            Stmt::Break(label) => visitor.visit_break(label.as_ref()),
+            Stmt::Continue(label) => visitor.visit_continue(label.as_ref()),
+            Stmt::Return(loc, value) => visitor.visit_return(loc, value.as_ref()),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FunDecl {
+    pub name: Symbol,
+    pub params: Vec<Symbol>,
+    /// The trailing `...rest` parameter, bound to a list of the extra
+    /// arguments.
+    pub rest: Option<Symbol>,
+    pub body: Vec<Stmt>,
 }
 
 #[derive(Debug, Clone)]
 pub enum Literal {
     String(String),
-    Number(f64),
+    /// The value and, for literals written in the source, its original
+    /// spelling such as `1.10` or `0o17`.
+    Number(f64, Option<String>),
     True,
     False,
     Nil,
@@ -55,11 +340,30 @@ pub enum UnaryOpType {
     Bang,
 }
 
+impl UnaryOpType {
+    /// The operator as written in the source.
+    pub fn symbol(self) -> &'static str {
+        match self {
+            UnaryOpType::Minus => "-",
+            UnaryOpType::Bang => "!",
+        }
+    }
+}
+
+/// Byte offsets into the source, `start..end`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct UnaryOp {
     pub op_type: UnaryOpType,
     pub line: usize,
     pub col: i64,
+    /// The operator together with its operand.
+    pub span: Span,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -74,6 +378,33 @@ pub enum BinaryOpType {
     Plus,
     Slash,
     Star,
+    /// `div`, floored division; `//` is already taken by comments.
+    Div,
+    /// `<<` and `>>`, on integer-valued numbers. `>>` is arithmetic, so it
+    /// keeps the sign.
+    ShiftLeft,
+    ShiftRight,
+}
+
+impl BinaryOpType {
+    /// The operator as written in the source.
+    pub fn symbol(self) -> &'static str {
+        match self {
+            BinaryOpType::BangEqual => "!=",
+            BinaryOpType::EqualEqual => "==",
+            BinaryOpType::Greater => ">",
+            BinaryOpType::GreaterEqual => ">=",
+            BinaryOpType::Less => "<",
+            BinaryOpType::LessEqual => "<=",
+            BinaryOpType::Minus => "-",
+            BinaryOpType::Plus => "+",
+            BinaryOpType::Slash => "/",
+            BinaryOpType::Star => "*",
+            BinaryOpType::Div => "div",
+            BinaryOpType::ShiftLeft => "<<",
+            BinaryOpType::ShiftRight => ">>",
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -81,4 +412,99 @@ pub struct BinaryOp {
     pub op_type: BinaryOpType,
     pub line: usize,
     pub col: i64,
+    /// The whole binary expression, both operands included.
+    pub span: Span,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Counts the nodes of an expression.
+    struct NodeCounter;
+
+    impl ExprVisitor for NodeCounter {
+        type Output = usize;
+
+        fn visit_assign(&mut self, _: &Symbol, value: &Expr) -> usize {
+            1 + value.accept(self)
+        }
+        fn visit_assign_destructure(
+            &mut self,
+            _: &SourceLocation,
+            _: &[Symbol],
+            value: &Expr,
+        ) -> usize {
+            1 + value.accept(self)
+        }
+        fn visit_unary(&mut self, _: &UnaryOp, operand: &Expr) -> usize {
+            1 + operand.accept(self)
+        }
+        fn visit_binary(&mut self, lhs: &Expr, _: &BinaryOp, rhs: &Expr) -> usize {
+            1 + lhs.accept(self) + rhs.accept(self)
+        }
+        fn visit_call(&mut self, callee: &Expr, _: &SourceLocation, args: &[Expr]) -> usize {
+            1 + callee.accept(self) + args.iter().map(|arg| arg.accept(self)).sum::<usize>()
+        }
+        fn visit_get(&mut self, object: &Expr, _: &Symbol, _: bool) -> usize {
+            1 + object.accept(self)
+        }
+        fn visit_index(&mut self, object: &Expr, _: &SourceLocation, index: &Expr) -> usize {
+            1 + object.accept(self) + index.accept(self)
+        }
+        fn visit_set_index(
+            &mut self,
+            object: &Expr,
+            _: &SourceLocation,
+            index: &Expr,
+            value: &Expr,
+        ) -> usize {
+            1 + object.accept(self) + index.accept(self) + value.accept(self)
+        }
+        fn visit_list(&mut self, elements: &[Expr]) -> usize {
+            1 + elements
+                .iter()
+                .map(|element| element.accept(self))
+                .sum::<usize>()
+        }
+        fn visit_comprehension(
+            &mut self,
+            element: &Expr,
+            _: &SourceLocation,
+            _: &Symbol,
+            iterable: &Expr,
+        ) -> usize {
+            1 + element.accept(self) + iterable.accept(self)
+        }
+        fn visit_grouping(&mut self, _: &SourceLocation, expr: &Expr) -> usize {
+            1 + expr.accept(self)
+        }
+        fn visit_literal(&mut self, _: &Literal) -> usize {
+            1
+        }
+        fn visit_logical(&mut self, lhs: &Expr, _: &LogicalOp, rhs: &Expr) -> usize {
+            1 + lhs.accept(self) + rhs.accept(self)
+        }
+        fn visit_set(&mut self, object: &Expr, _: &Symbol, value: &Expr) -> usize {
+            1 + object.accept(self) + value.accept(self)
+        }
+        fn visit_super(&mut self, _: &SourceLocation, _: &Symbol) -> usize {
+            1
+        }
+        fn visit_this(&mut self, _: &SourceLocation) -> usize {
+            1
+        }
+        fn visit_variable(&mut self, _: &Symbol) -> usize {
+            1
+        }
+    }
+
+    #[test]
+    fn a_visitor_sees_every_node() {
+        let tokens = crate::scanner::scan_tokens("f(1 + 2, -x)".to_string()).unwrap();
+        let expr = crate::parser::Parser::new(tokens)
+            .parse_expression()
+            .unwrap();
+        assert_eq!(expr.accept(&mut NodeCounter), 7);
+    }
 }
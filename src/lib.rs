@@ -0,0 +1,188 @@
+pub mod ast_printer;
+pub mod error_format;
+pub mod expr;
+pub mod natives;
+pub mod optimizer;
+pub mod parser;
+pub mod repl;
+pub mod resolver;
+pub mod scanner;
+pub mod tree_interpreter;
+
+#[cfg(test)]
+mod test_support;
+
+use error_format::{Diagnostic, ErrorKind};
+use expr::Stmt;
+use parser::Parser;
+use resolver::Lints;
+use tree_interpreter::{Interpreter, Value};
+
+/// Scans and parses `source`, stopping after the first stage that fails.
+pub fn parse(source: String) -> Result<Vec<Stmt>, Vec<Diagnostic>> {
+    parse_scanned(scanner::scan_tokens(source), false)
+}
+
+/// Like `parse`, with automatic semicolon insertion: a line break that
+/// ends a complete statement counts as its `;`.
+pub fn parse_asi(source: String) -> Result<Vec<Stmt>, Vec<Diagnostic>> {
+    parse_scanned(scanner::scan_tokens_with_newlines(source), true)
+}
+
+/// Only scans `source`, for tools that want the tokens themselves.
+pub fn tokenize(source: String) -> Result<Vec<scanner::Token>, Vec<Diagnostic>> {
+    scanner::scan_tokens(source).map_err(scan_diagnostics)
+}
+
+fn scan_diagnostics(errors: Vec<scanner::Error>) -> Vec<Diagnostic> {
+    errors
+        .into_iter()
+        .map(|err| Diagnostic {
+            kind: ErrorKind::Scan,
+            message: err.message,
+            line: err.line,
+            col: err.col,
+        })
+        .collect()
+}
+
+fn parse_scanned(
+    scanned: Result<Vec<scanner::Token>, Vec<scanner::Error>>,
+    asi: bool,
+) -> Result<Vec<Stmt>, Vec<Diagnostic>> {
+    parse_tokens(scanned.map_err(scan_diagnostics)?, asi)
+}
+
+fn parse_tokens(tokens: Vec<scanner::Token>, asi: bool) -> Result<Vec<Stmt>, Vec<Diagnostic>> {
+    Parser::with_asi(tokens, asi)
+        .parse()
+        .map_err(|errors| errors.iter().map(parse_diagnostic).collect())
+}
+
+fn parse_diagnostic(err: &parser::Error) -> Diagnostic {
+    let (line, col) = err.location();
+    Diagnostic {
+        kind: ErrorKind::Parse,
+        message: err.message(),
+        line,
+        col,
+    }
+}
+
+/// Evaluates `source` as a single expression, such as `2 * (3 + 4)`, in a
+/// fresh interpreter and returns its value. Statements aren't allowed, so
+/// there is no trailing `;`.
+pub fn eval_expr(source: &str) -> Result<Value, Vec<Diagnostic>> {
+    let tokens = scanner::scan_tokens(source.to_string()).map_err(scan_diagnostics)?;
+    let expr = Parser::new(tokens)
+        .parse_expression()
+        .map_err(|err| vec![parse_diagnostic(&err)])?;
+    Interpreter::default().evaluate_expr(&expr).map_err(|err| {
+        vec![Diagnostic {
+            kind: ErrorKind::Runtime,
+            message: err.message,
+            line: err.line,
+            col: err.col,
+        }]
+    })
+}
+
+/// Parses `line`, adding the trailing `;` it may be missing so `print 1`
+/// works at the prompt. The `;` is only kept if the line then parses, so
+/// any other mistake is reported as typed. `asi` picks `parse_asi`.
+pub(crate) fn parse_line(line: &str, asi: bool) -> Result<Vec<Stmt>, Vec<Diagnostic>> {
+    let scanned = if asi {
+        scanner::scan_tokens_with_newlines(line.to_string())
+    } else {
+        scanner::scan_tokens(line.to_string())
+    };
+    parse_line_tokens(scanned.map_err(scan_diagnostics)?, asi)
+}
+
+/// `parse_line` for a line that is already scanned.
+pub(crate) fn parse_line_tokens(
+    mut tokens: Vec<scanner::Token>,
+    asi: bool,
+) -> Result<Vec<Stmt>, Vec<Diagnostic>> {
+    let parsed = parse_tokens(tokens.clone(), asi);
+    let last = tokens.iter().rposition(|token| {
+        !matches!(
+            token.t_type,
+            scanner::TokenType::Eof | scanner::TokenType::Newline
+        )
+    });
+    let Some(last) = last else {
+        return parsed;
+    };
+    let after = &tokens[last];
+    if parsed.is_ok()
+        || matches!(
+            after.t_type,
+            scanner::TokenType::Semicolon | scanner::TokenType::RightBrace
+        )
+    {
+        return parsed;
+    }
+    let semicolon = scanner::Token {
+        t_type: scanner::TokenType::Semicolon,
+        lexeme: b";".to_vec(),
+        line: after.line,
+        col: after.col + after.lexeme.len() as i64,
+        start: after.end,
+        end: after.end,
+        literal: None,
+    };
+    tokens.insert(last + 1, semicolon);
+    parse_tokens(tokens, asi).or(parsed)
+}
+
+/// Runs every static check on `source` without executing any of it.
+pub fn check(source: String) -> Result<Vec<Stmt>, Vec<Diagnostic>> {
+    check_program(parse(source)?, Lints::default())
+}
+
+/// The checks `check` runs after parsing, with `lints` turned on as well.
+pub fn check_program(program: Vec<Stmt>, lints: Lints) -> Result<Vec<Stmt>, Vec<Diagnostic>> {
+    check_program_in(program, lints, &[])
+}
+
+/// Like `check_program`, with `known` globals already defined.
+pub(crate) fn check_program_in(
+    program: Vec<Stmt>,
+    lints: Lints,
+    known: &[String],
+) -> Result<Vec<Stmt>, Vec<Diagnostic>> {
+    resolver::resolve_in(&program, lints, known).map_err(|errors| {
+        errors
+            .into_iter()
+            .map(|err| Diagnostic {
+                kind: ErrorKind::Resolve,
+                message: err.message,
+                line: err.line,
+                col: err.col,
+            })
+            .collect::<Vec<_>>()
+    })?;
+    Ok(program)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_flags_static_errors_without_running_anything() {
+        assert!(check("print 1 / 0;".to_string()).is_ok());
+        let errors = check("print 1 / 0; print missing;".to_string()).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].kind, ErrorKind::Resolve);
+        assert_eq!(errors[0].message, "Undefined variable 'missing'");
+    }
+
+    #[test]
+    fn eval_expr_returns_the_value() {
+        assert!(matches!(eval_expr("2 * (3 + 4)"), Ok(Value::Number(n)) if n == 14.0));
+        let errors = eval_expr("1 + 2;").unwrap_err();
+        assert_eq!(errors[0].kind, ErrorKind::Parse);
+    }
+}
@@ -1,69 +1,419 @@
+use std::error::Error;
 use std::fs::File;
-use std::io::{self, stdin, stdout, BufRead, BufReader, Error, Read, Write};
+use std::io::{stdin, stdout, IsTerminal, Read, Write};
 use std::{env, process};
 
-use parser::Parser;
-
-mod error_format;
-mod expr;
-mod parser;
-mod scanner;
-mod tree_interpreter;
+use rust_craft::error_format::{self, Diagnostic, ErrorKind};
+use rust_craft::optimizer;
+use rust_craft::repl::Repl;
+use rust_craft::resolver::Lints;
+use rust_craft::scanner::Scanner;
+use rust_craft::tree_interpreter::{Interpreter, Profile, ScientificThresholds};
 
 const AUTHORS: &str = env!("CARGO_PKG_AUTHORS");
 const VERSION: &str = env!("CARGO_PKG_VERSION");
+/// What the REPL prompts with unless `--prompt` says otherwise.
+const DEFAULT_PROMPT: &str = "> ";
+/// How many errors are shown unless `--max-errors` says otherwise.
+const DEFAULT_MAX_ERRORS: usize = 20;
+
+/// How deep Lox calls may nest in `lox`, far past the library default
+/// because scripts run on a thread with a `STACK_SIZE` stack.
+const MAX_CALL_DEPTH: usize = 10_000;
+/// Stack for the thread scripts run on, enough for `MAX_CALL_DEPTH` nested
+/// calls even in a debug build. Only the pages actually used are committed.
+const STACK_SIZE: usize = 512 * 1024 * 1024;
+
+fn main() {
+    let worker = std::thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(|| {
+            let result = run();
+            if let Err(err) = &result {
+                // In JSON mode the diagnostics were already written as JSON.
+                if !error_format::json_errors() {
+                    eprintln!("Error: {}", err);
+                }
+            }
+            result.is_ok()
+        })
+        .expect("failed to start the interpreter thread");
+    if !worker.join().unwrap_or(false) {
+        process::exit(1);
+    }
+}
+
+/// Reports up to `max_errors` errors and returns a summary for `main` to
+/// exit with.
+fn report_errors(errors: &[Diagnostic], max_errors: usize) -> Box<dyn Error> {
+    for err in errors.iter().take(max_errors) {
+        error_format::format_error(err.kind, &err.message, err.line, err.col);
+    }
+    error_format::flush_errors();
+    if errors.len() > max_errors && !error_format::json_errors() {
+        eprintln!("... and {} more errors", errors.len() - max_errors);
+    }
+    format!("aborting due to {} previous error(s)", errors.len()).into()
+}
+
+/// The token dump is meant for other programs, so nothing else goes to
+/// stdout with it. Piped input is most likely another program too.
+fn show_banner(quiet: bool, emit_tokens_json: bool, interactive: bool) -> bool {
+    !quiet && !emit_tokens_json && interactive
+}
+
+/// Every flag, with the value it takes if any and what it does, in the
+/// order `--help` lists them.
+const FLAGS: &[(&str, &str, &str)] = &[
+    ("--help", "", "Show this help and exit"),
+    (
+        "--check",
+        "",
+        "Run the static checks only, without executing",
+    ),
+    ("--json-errors", "", "Report errors as JSON"),
+    ("--max-errors", "N", "Show at most N errors"),
+    (
+        "--asi",
+        "",
+        "Treat a line break that ends a statement as its ';'",
+    ),
+    ("--optimize", "", "Fold constant expressions before running"),
+    (
+        "--profile",
+        "",
+        "Count the evaluated nodes and print the counts",
+    ),
+    ("--trace", "", "Print each statement before it runs"),
+    ("--stats", "", "Print token and line counts"),
+    (
+        "--emit-tokens-json",
+        "",
+        "Print the tokens as JSON and exit",
+    ),
+    (
+        "--scientific",
+        "",
+        "Print very large and small numbers in exponential form",
+    ),
+    (
+        "--float-precision",
+        "N",
+        "Print non-integers with N significant digits",
+    ),
+    ("--loose-nil", "", "Treat nil as 0 or \"\" in arithmetic"),
+    ("--sandbox", "", "Deny natives that reach the host"),
+    (
+        "--dump-env-on-error",
+        "",
+        "Print the variables in scope on a runtime error",
+    ),
+    (
+        "--warn-unused-results",
+        "",
+        "Warn about unused expression results",
+    ),
+    ("--warn-shadowing", "", "Warn about shadowed variables"),
+    ("--prompt", "TEXT", "Prompt the REPL with TEXT"),
+    ("--quiet", "", "Don't print the banner"),
+];
+
+/// The usage line and every flag from `FLAGS`.
+fn usage() -> String {
+    let mut usage = String::from("Usage: lox [flags] [script]\n\nFlags:\n");
+    for (flag, value, help) in FLAGS {
+        let flag = if value.is_empty() {
+            flag.to_string()
+        } else {
+            format!("{} {}", flag, value)
+        };
+        usage.push_str(&format!("  {:<24}{}\n", flag, help));
+    }
+    usage
+}
+
+/// The parsed command line.
+#[derive(Default)]
+struct Args {
+    help: bool,
+    check_only: bool,
+    json_errors: bool,
+    max_errors: Option<usize>,
+    asi: bool,
+    optimize: bool,
+    profile: bool,
+    trace: bool,
+    stats: bool,
+    emit_tokens_json: bool,
+    scientific: bool,
+    float_precision: Option<usize>,
+    loose_nil: bool,
+    sandbox: bool,
+    dump_env_on_error: bool,
+    lints: Lints,
+    prompt: Option<String>,
+    quiet: bool,
+    /// Everything that isn't a flag, starting with the program name.
+    positional: Vec<String>,
+}
+
+impl Args {
+    fn parse(mut raw_args: impl Iterator<Item = String>) -> Result<Args, String> {
+        let mut args = Args::default();
+        while let Some(arg) = raw_args.next() {
+            let mut number = |flag: &str| {
+                raw_args
+                    .next()
+                    .and_then(|n| n.parse().ok())
+                    .ok_or(format!("{} expects a number", flag))
+            };
+            match arg.as_str() {
+                "--help" => args.help = true,
+                "--check" => args.check_only = true,
+                "--json-errors" => args.json_errors = true,
+                "--max-errors" => args.max_errors = Some(number("--max-errors")?),
+                "--asi" => args.asi = true,
+                "--optimize" => args.optimize = true,
+                "--profile" => args.profile = true,
+                "--trace" => args.trace = true,
+                "--stats" => args.stats = true,
+                "--emit-tokens-json" => args.emit_tokens_json = true,
+                "--scientific" => args.scientific = true,
+                "--float-precision" => match number("--float-precision")? {
+                    0 => return Err("--float-precision must be at least 1".to_string()),
+                    digits => args.float_precision = Some(digits),
+                },
+                "--loose-nil" => args.loose_nil = true,
+                "--sandbox" => args.sandbox = true,
+                "--dump-env-on-error" => args.dump_env_on_error = true,
+                "--warn-unused-results" => args.lints.unused_results = true,
+                "--warn-shadowing" => args.lints.shadowing = true,
+                "--prompt" => {
+                    args.prompt = Some(raw_args.next().ok_or("--prompt expects a string")?);
+                }
+                "--quiet" => args.quiet = true,
+                _ if arg.starts_with("--") => return Err(format!("unknown flag {}", arg)),
+                _ => args.positional.push(arg),
+            }
+        }
+        Ok(args)
+    }
+}
+
+impl Args {
+    /// Applies the flags that set up the interpreter, for scripts and the
+    /// REPL alike.
+    fn configure(&self, interpreter: &mut Interpreter) {
+        interpreter.max_call_depth = MAX_CALL_DEPTH;
+        if self.profile {
+            interpreter.profile = Some(Profile::default());
+        }
+        if self.sandbox {
+            interpreter.enable_sandbox();
+        }
+        interpreter.trace = self.trace;
+        interpreter.dump_env_on_error = self.dump_env_on_error;
+        interpreter.float_precision = self.float_precision;
+        interpreter.loose_nil_arithmetic = self.loose_nil;
+        if self.scientific {
+            interpreter.scientific_notation = Some(ScientificThresholds::default());
+        }
+    }
+}
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    println!(
-        "===================================================\n\
-        Welcome to lox {}!\n\
-        Inspired by the book \x1B[3mCrafting Interpreters\x1B[0m\nAuthors: {}\n\
-        ===================================================\n",
-        VERSION, AUTHORS
-    );
+fn print_profile(interpreter: &Interpreter) {
+    if let Some(profile) = &interpreter.profile {
+        eprint!("Profile:\n{}", profile);
+    }
+}
 
-    let args: Vec<String> = env::args().collect();
+fn run() -> Result<(), Box<dyn Error>> {
+    let parsed = Args::parse(env::args()).map_err(|err| format!("{}\n\n{}", err, usage()))?;
+    let Args {
+        help,
+        check_only,
+        json_errors,
+        max_errors,
+        asi,
+        optimize,
+        stats,
+        emit_tokens_json,
+        lints,
+        quiet,
+        ..
+    } = parsed;
+    let args = &parsed.positional;
+    if help {
+        print!("{}", usage());
+        return Ok(());
+    }
+    error_format::set_json_errors(json_errors);
+    let max_errors = max_errors.unwrap_or(DEFAULT_MAX_ERRORS);
+    let prompt = parsed.prompt.as_deref().unwrap_or(DEFAULT_PROMPT);
+    if show_banner(quiet, emit_tokens_json, stdin().is_terminal()) {
+        println!(
+            "===================================================\n\
+            Welcome to lox {}!\n\
+            Inspired by the book \x1B[3mCrafting Interpreters\x1B[0m\nAuthors: {}\n\
+            ===================================================\n",
+            VERSION, AUTHORS
+        );
+    }
     if args.len() == 1 {
+        let mut repl = Repl::default();
+        repl.asi = asi;
+        repl.optimize = optimize;
+        repl.lints = lints;
+        parsed.configure(&mut repl.interpreter);
         loop {
-            print!("> ");
+            print!("{}", if repl.is_continuing() { ". " } else { prompt });
             stdout().flush()?;
             let mut chars = String::new();
             stdin().read_line(&mut chars).expect("Failed to read input");
             let content = chars.trim();
-            if content.len() == 0 {
+            if content.is_empty() {
                 println!("No input.");
+                print_profile(&repl.interpreter);
                 process::exit(1);
             }
             if content.to_lowercase() == "bye" {
                 println!("Exit REPL.");
+                print_profile(&repl.interpreter);
                 std::process::exit(1);
             }
-            println!("input: {}", content);
+            if let Err(errors) = repl.run_line(content) {
+                for err in &errors {
+                    error_format::format_error(err.kind, &err.message, err.line, err.col);
+                }
+                error_format::flush_errors();
+            }
         }
     } else if args.len() != 2 {
-        eprintln!("Usage: lox [script]");
+        eprint!("{}", usage());
         process::exit(1);
     }
 
     let source_path = args.get(1).unwrap();
-    println!("source file: {}", source_path);
+    if !emit_tokens_json {
+        println!("source file: {}", source_path);
+    }
     let mut file = File::open(source_path)?;
     let mut content = String::new();
     file.read_to_string(&mut content)?;
 
-    let mut scanner = crate::scanner::Scanner::new(content.clone());
-    let tokens = scanner::scan_tokens(content).unwrap();
-    // println!("Tokens: {:?}", tokens);
-    let mut parser = Parser { tokens, current: 0 };
-    let program = parser.parse().map_err(|e| {
-        // println!("Parsed Expr: {:?}", e);
-        "Parse error.".to_string()
-    })?;
+    if emit_tokens_json {
+        let tokens =
+            rust_craft::tokenize(content).map_err(|errors| report_errors(&errors, max_errors))?;
+        let entries: Vec<_> = tokens.iter().map(|token| token.to_json()).collect();
+        println!("[\n  {}\n]", entries.join(",\n  "));
+        return Ok(());
+    }
+
+    if stats {
+        let mut scanner = Scanner::default();
+        scanner.scan_tokens(content.clone());
+        println!(
+            "{} tokens across {} lines",
+            scanner.token_count(),
+            scanner.line_count()
+        );
+    }
+
+    let parse = if asi {
+        rust_craft::parse_asi
+    } else {
+        rust_craft::parse
+    };
+    let program = parse(content).map_err(|errors| report_errors(&errors, max_errors))?;
+    let program = rust_craft::check_program(program, lints)
+        .map_err(|errors| report_errors(&errors, max_errors))?;
+    // `--check` stops after the static checks, so nothing is executed.
+    if check_only {
+        error_format::flush_errors();
+        return Ok(());
+    }
+    let program = if optimize {
+        optimizer::fold_constants(&program)
+    } else {
+        program
+    };
     // println!("Parsed Expr: {:?}", &program);
 
-    let mut interpreter = tree_interpreter::Interpreter::default();
+    let mut interpreter = Interpreter::default();
+    parsed.configure(&mut interpreter);
     if let Err(e) = interpreter.interpret(&program) {
-        eprintln!("Eval err: {}", e);
+        if error_format::json_errors() {
+            error_format::format_error(ErrorKind::Runtime, &e.message, e.line, e.col);
+        } else {
+            eprintln!("Eval err: {}", e);
+        }
+        if let Some(bindings) = &e.bindings {
+            eprint!("Environment at the error:\n{}", bindings);
+        }
     }
+    error_format::flush_errors();
+    print_profile(&interpreter);
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Args, String> {
+        Args::parse(
+            std::iter::once("lox")
+                .chain(args.iter().copied())
+                .map(String::from),
+        )
+    }
+
+    #[test]
+    fn help_lists_the_usage_and_every_flag() {
+        let usage = usage();
+        assert!(usage.starts_with("Usage: lox [flags] [script]"));
+        for (flag, _, _) in FLAGS {
+            assert!(usage.contains(flag), "{} is missing", flag);
+        }
+        assert!(parse(&["--help"]).unwrap().help);
+    }
+
+    #[test]
+    fn flags_and_the_script_are_parsed() {
+        let args = parse(&["--sandbox", "--optimize", "--max-errors", "3", "script.lox"]).unwrap();
+        assert!(args.sandbox);
+        assert!(args.optimize);
+        assert_eq!(args.max_errors, Some(3));
+        assert_eq!(args.positional, ["lox", "script.lox"]);
+    }
+
+    #[test]
+    fn unknown_flags_are_rejected() {
+        assert_eq!(parse(&["--sandbx"]).err().unwrap(), "unknown flag --sandbx");
+    }
+
+    #[test]
+    fn flag_values_must_parse() {
+        assert_eq!(
+            parse(&["--max-errors", "x"]).err().unwrap(),
+            "--max-errors expects a number"
+        );
+        assert_eq!(
+            parse(&["--float-precision", "0"]).err().unwrap(),
+            "--float-precision must be at least 1"
+        );
+        assert_eq!(
+            parse(&["--float-precision", "2"]).unwrap().float_precision,
+            Some(2)
+        );
+    }
+
+    #[test]
+    fn quiet_turns_the_banner_off() {
+        assert!(show_banner(false, false, true));
+        assert!(!show_banner(true, false, true));
+        assert!(!show_banner(false, true, true));
+        assert!(!show_banner(false, false, false));
+        assert!(parse(&["--quiet"]).unwrap().quiet);
+    }
+}
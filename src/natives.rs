@@ -0,0 +1,1282 @@
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::io::Write;
+use std::rc::Rc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::expr::Symbol;
+use crate::tree_interpreter::{
+    instance_of, Arity, Environment, Interpreter, LoxMap, LoxType, NativeFunction, Range, Value,
+};
+
+/// Time source behind `clock`, `clock_millis` and `sleep`.
+pub trait Clock {
+    /// Seconds since the Unix epoch.
+    fn now(&self) -> f64;
+    /// Milliseconds since the clock was created. Unlike `now`, this never
+    /// goes backwards when the system time is adjusted.
+    fn millis(&self) -> f64;
+    fn sleep(&mut self, seconds: f64);
+}
+
+pub struct SystemClock {
+    started: Instant,
+}
+
+impl Default for SystemClock {
+    fn default() -> Self {
+        SystemClock {
+            started: Instant::now(),
+        }
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> f64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs_f64())
+            .unwrap_or(0.0)
+    }
+
+    fn millis(&self) -> f64 {
+        self.started.elapsed().as_secs_f64() * 1000.0
+    }
+
+    fn sleep(&mut self, seconds: f64) {
+        thread::sleep(Duration::from_secs_f64(seconds));
+    }
+}
+
+/// A clock that only moves when slept on, so scripts using `sleep` don't
+/// block when run from tests.
+#[derive(Default)]
+pub struct VirtualClock {
+    pub now: f64,
+}
+
+impl Clock for VirtualClock {
+    fn now(&self) -> f64 {
+        self.now
+    }
+
+    fn millis(&self) -> f64 {
+        self.now * 1000.0
+    }
+
+    fn sleep(&mut self, seconds: f64) {
+        self.now += seconds;
+    }
+}
+
+/// A small xorshift64* generator behind `random` and `srand`, so seeded
+/// scripts see the same sequence on every run.
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        // Spread the seed with splitmix64, since xorshift needs a non-zero
+        // state and nearby seeds would otherwise start out alike.
+        let mut z = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        Rng {
+            state: (z ^ (z >> 31)).max(1),
+        }
+    }
+
+    /// A float in [0, 1).
+    pub fn next_f64(&mut self) -> f64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        let bits = self.state.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 11;
+        bits as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// Seeded from the system time; call `srand` for a repeatable sequence.
+impl Default for Rng {
+    fn default() -> Self {
+        Rng::new(SystemClock::default().now().to_bits())
+    }
+}
+
+/// Natives that reach outside the interpreter, to its input or by
+/// blocking the thread.
+const HOST_NATIVES: [&str; 2] = ["read_line", "sleep"];
+
+/// Swaps the natives that touch the host for ones that fail when called.
+/// Their names and arities stay, so a script resolves the same either way.
+pub fn sandbox(env: &mut Environment) {
+    for name in HOST_NATIVES {
+        let symbol = Symbol {
+            name: name.to_string(),
+            line: 0,
+            col: -1,
+        };
+        let Ok(Value::NativeFunction(native)) = env.get(&symbol) else {
+            continue;
+        };
+        let denied = NativeFunction {
+            callable: Rc::new(move |_, _| {
+                Err(format!("{}: operation not permitted in sandbox", name))
+            }),
+            ..native
+        };
+        env.define(symbol, Some(Value::NativeFunction(denied)));
+    }
+}
+
+pub fn define_globals(env: &mut Environment) {
+    let natives = [
+        NativeFunction {
+            name: "assert_eq".to_string(),
+            arity: Arity::Exactly(2),
+            callable: Rc::new(assert_eq),
+        },
+        NativeFunction {
+            name: "assert_throws".to_string(),
+            arity: Arity::Exactly(1),
+            callable: Rc::new(assert_throws),
+        },
+        NativeFunction {
+            name: "benchmark".to_string(),
+            arity: Arity::Exactly(2),
+            callable: Rc::new(benchmark),
+        },
+        NativeFunction {
+            name: "bytes_of".to_string(),
+            arity: Arity::Exactly(1),
+            callable: Rc::new(bytes_of),
+        },
+        NativeFunction {
+            name: "clock".to_string(),
+            arity: Arity::Exactly(0),
+            callable: Rc::new(clock),
+        },
+        NativeFunction {
+            name: "clock_millis".to_string(),
+            arity: Arity::Exactly(0),
+            callable: Rc::new(clock_millis),
+        },
+        NativeFunction {
+            name: "concat".to_string(),
+            arity: Arity::AtLeast(1),
+            callable: Rc::new(concat),
+        },
+        NativeFunction {
+            name: "contains".to_string(),
+            arity: Arity::Exactly(2),
+            callable: Rc::new(contains),
+        },
+        NativeFunction {
+            name: "eval".to_string(),
+            arity: Arity::Exactly(1),
+            callable: Rc::new(eval),
+        },
+        NativeFunction {
+            name: "filter".to_string(),
+            arity: Arity::Exactly(2),
+            callable: Rc::new(filter),
+        },
+        NativeFunction {
+            name: "floordiv".to_string(),
+            arity: Arity::Exactly(2),
+            callable: Rc::new(floordiv),
+        },
+        NativeFunction {
+            name: "floormod".to_string(),
+            arity: Arity::Exactly(2),
+            callable: Rc::new(floormod),
+        },
+        NativeFunction {
+            name: "format".to_string(),
+            arity: Arity::AtLeast(1),
+            callable: Rc::new(format),
+        },
+        NativeFunction {
+            name: "from_base64".to_string(),
+            arity: Arity::Exactly(1),
+            callable: Rc::new(from_base64),
+        },
+        NativeFunction {
+            name: "globals".to_string(),
+            arity: Arity::Exactly(0),
+            callable: Rc::new(globals),
+        },
+        NativeFunction {
+            name: "is_bool".to_string(),
+            arity: Arity::Exactly(1),
+            callable: Rc::new(is_bool),
+        },
+        NativeFunction {
+            name: "is_nil".to_string(),
+            arity: Arity::Exactly(1),
+            callable: Rc::new(is_nil),
+        },
+        NativeFunction {
+            name: "is_number".to_string(),
+            arity: Arity::Exactly(1),
+            callable: Rc::new(is_number),
+        },
+        NativeFunction {
+            name: "is_string".to_string(),
+            arity: Arity::Exactly(1),
+            callable: Rc::new(is_string),
+        },
+        NativeFunction {
+            name: "lower".to_string(),
+            arity: Arity::Exactly(1),
+            callable: Rc::new(string_lower),
+        },
+        NativeFunction {
+            name: "map".to_string(),
+            arity: Arity::Exactly(2),
+            callable: Rc::new(map),
+        },
+        NativeFunction {
+            name: "max".to_string(),
+            arity: Arity::AtLeast(1),
+            callable: Rc::new(max),
+        },
+        NativeFunction {
+            name: "min".to_string(),
+            arity: Arity::AtLeast(1),
+            callable: Rc::new(min),
+        },
+        NativeFunction {
+            name: "new_map".to_string(),
+            arity: Arity::Exactly(0),
+            callable: Rc::new(new_map),
+        },
+        NativeFunction {
+            name: "panic".to_string(),
+            arity: Arity::Exactly(1),
+            callable: Rc::new(panic),
+        },
+        NativeFunction {
+            name: "printf".to_string(),
+            arity: Arity::AtLeast(1),
+            callable: Rc::new(printf),
+        },
+        NativeFunction {
+            name: "random".to_string(),
+            arity: Arity::Exactly(0),
+            callable: Rc::new(random),
+        },
+        NativeFunction {
+            name: "range".to_string(),
+            arity: Arity::Between(2, 3),
+            callable: Rc::new(range),
+        },
+        NativeFunction {
+            name: "read_line".to_string(),
+            arity: Arity::Exactly(0),
+            callable: Rc::new(read_line),
+        },
+        NativeFunction {
+            name: "reduce".to_string(),
+            arity: Arity::Exactly(3),
+            callable: Rc::new(reduce),
+        },
+        NativeFunction {
+            name: "sizeof".to_string(),
+            arity: Arity::Exactly(1),
+            callable: Rc::new(sizeof),
+        },
+        NativeFunction {
+            name: "sleep".to_string(),
+            arity: Arity::Exactly(1),
+            callable: Rc::new(sleep),
+        },
+        NativeFunction {
+            name: "sort".to_string(),
+            arity: Arity::Exactly(1),
+            callable: Rc::new(sort),
+        },
+        NativeFunction {
+            name: "split".to_string(),
+            arity: Arity::Exactly(2),
+            callable: Rc::new(split),
+        },
+        NativeFunction {
+            name: "srand".to_string(),
+            arity: Arity::Exactly(1),
+            callable: Rc::new(srand),
+        },
+        NativeFunction {
+            name: "to_base64".to_string(),
+            arity: Arity::Exactly(1),
+            callable: Rc::new(to_base64),
+        },
+        NativeFunction {
+            name: "trim".to_string(),
+            arity: Arity::Exactly(1),
+            callable: Rc::new(trim),
+        },
+        NativeFunction {
+            name: "upper".to_string(),
+            arity: Arity::Exactly(1),
+            callable: Rc::new(string_upper),
+        },
+    ];
+    for native in natives {
+        env.define(
+            Symbol {
+                name: native.name.clone(),
+                line: 0,
+                col: -1,
+            },
+            Some(Value::NativeFunction(native)),
+        );
+    }
+}
+
+/// The properties shared by every value, such as `to_string`.
+pub fn value_property(value: &Value, name: &str) -> Option<Value> {
+    match name {
+        "to_string" => Some(Value::BoundMethod(
+            Box::new(value.clone()),
+            NativeFunction {
+                name: "to_string".to_string(),
+                arity: Arity::Exactly(0),
+                callable: Rc::new(to_string),
+            },
+        )),
+        _ => None,
+    }
+}
+
+/// The same text `print` shows for the value.
+fn to_string(interpreter: &mut Interpreter, args: &[Value]) -> Result<Value, String> {
+    Ok(Value::String(interpreter.format_val(&args[0])))
+}
+
+/// The built-in properties of strings; methods come back bound to `s`.
+pub fn string_property(s: &str, name: &str) -> Option<Value> {
+    let method = |name: &str, arity, callable: fn(&mut Interpreter, &[Value]) -> _| {
+        Some(Value::BoundMethod(
+            Box::new(Value::String(s.to_string())),
+            NativeFunction {
+                name: name.to_string(),
+                arity: Arity::Exactly(arity),
+                callable: Rc::new(callable),
+            },
+        ))
+    };
+    match name {
+        "length" => Some(Value::Number(s.chars().count() as f64)),
+        "upper" => method("upper", 0, string_upper),
+        "lower" => method("lower", 0, string_lower),
+        "trim" => method("trim", 0, trim),
+        "split" => method("split", 1, split),
+        _ => None,
+    }
+}
+
+/// The built-in properties of maps; methods come back bound to `map`.
+pub fn map_property(map: &Rc<RefCell<LoxMap>>, name: &str) -> Option<Value> {
+    let method = |name: &str, arity, callable: fn(&mut Interpreter, &[Value]) -> _| {
+        Some(Value::BoundMethod(
+            Box::new(Value::Map(map.clone())),
+            NativeFunction {
+                name: name.to_string(),
+                arity: Arity::Exactly(arity),
+                callable: Rc::new(callable),
+            },
+        ))
+    };
+    match name {
+        "length" => Some(Value::Number(map.borrow().len() as f64)),
+        "get" => method("get", 1, map_get),
+        "set" => method("set", 2, map_set),
+        "keys" => method("keys", 0, map_keys),
+        _ => None,
+    }
+}
+
+fn new_map(_interpreter: &mut Interpreter, _args: &[Value]) -> Result<Value, String> {
+    Ok(Value::Map(Rc::new(RefCell::new(LoxMap::default()))))
+}
+
+/// The receiver of map methods is always a map, `map_property` binds it.
+fn receiver_map(args: &[Value]) -> &Rc<RefCell<LoxMap>> {
+    match &args[0] {
+        Value::Map(map) => map,
+        val => unreachable!("map method bound to {}", instance_of(val)),
+    }
+}
+
+/// The value stored under the key, or `nil` if there is none.
+fn map_get(_interpreter: &mut Interpreter, args: &[Value]) -> Result<Value, String> {
+    let map = receiver_map(args).borrow();
+    Ok(map.get(&args[1]).cloned().unwrap_or(Value::Nil))
+}
+
+fn map_set(_interpreter: &mut Interpreter, args: &[Value]) -> Result<Value, String> {
+    receiver_map(args)
+        .borrow_mut()
+        .set(args[1].clone(), args[2].clone());
+    Ok(Value::Nil)
+}
+
+/// The keys in the order they were first set.
+fn map_keys(_interpreter: &mut Interpreter, args: &[Value]) -> Result<Value, String> {
+    let keys = receiver_map(args).borrow().keys().cloned().collect();
+    Ok(Value::List(Rc::new(RefCell::new(keys))))
+}
+
+/// Backs both `upper(s)` and `s.upper()`. Uses the full Unicode mapping,
+/// so the result can be longer than `s`: `upper("ß")` is `"SS"`.
+fn string_upper(_interpreter: &mut Interpreter, args: &[Value]) -> Result<Value, String> {
+    match &args[0] {
+        Value::String(s) => Ok(Value::String(s.to_uppercase())),
+        val => Err(format!("upper expects a string, got {}", instance_of(val))),
+    }
+}
+
+fn string_lower(_interpreter: &mut Interpreter, args: &[Value]) -> Result<Value, String> {
+    match &args[0] {
+        Value::String(s) => Ok(Value::String(s.to_lowercase())),
+        val => Err(format!("lower expects a string, got {}", instance_of(val))),
+    }
+}
+
+/// `s` without leading and trailing whitespace. Backs both `trim(s)` and
+/// `s.trim()`.
+fn trim(_interpreter: &mut Interpreter, args: &[Value]) -> Result<Value, String> {
+    match &args[0] {
+        Value::String(s) => Ok(Value::String(s.trim().to_string())),
+        val => Err(format!("trim expects a string, got {}", instance_of(val))),
+    }
+}
+
+/// The pieces of `s` between occurrences of `sep`. An empty separator
+/// splits `s` into its characters.
+fn split(_interpreter: &mut Interpreter, args: &[Value]) -> Result<Value, String> {
+    let (s, sep) = match (&args[0], &args[1]) {
+        (Value::String(s), Value::String(sep)) => (s, sep),
+        (Value::String(_), val) => {
+            return Err(format!(
+                "split expects a string separator, got {}",
+                instance_of(val)
+            ))
+        }
+        (val, _) => return Err(format!("split expects a string, got {}", instance_of(val))),
+    };
+    let pieces = if sep.is_empty() {
+        s.chars().map(|c| Value::String(c.to_string())).collect()
+    } else {
+        s.split(sep.as_str())
+            .map(|piece| Value::String(piece.to_string()))
+            .collect()
+    };
+    Ok(Value::List(Rc::new(RefCell::new(pieces))))
+}
+
+/// Whether a string has `item` as a substring, or a list has an element
+/// `==` to it.
+fn contains(_interpreter: &mut Interpreter, args: &[Value]) -> Result<Value, String> {
+    match (&args[0], &args[1]) {
+        (Value::String(s), Value::String(sub)) => Ok(Value::Boolean(s.contains(sub.as_str()))),
+        (Value::String(_), item) => Err(format!(
+            "contains can only look for a string in a string, got {}",
+            instance_of(item)
+        )),
+        (Value::List(elements), item) => Ok(Value::Boolean(
+            elements
+                .borrow()
+                .iter()
+                .any(|element| Interpreter::equals(element, item)),
+        )),
+        (val, _) => Err(format!(
+            "contains expects a string or a list, got {}",
+            instance_of(val)
+        )),
+    }
+}
+
+/// Joins strings into one string, or lists into one new list. The
+/// arguments must all be strings or all be lists.
+fn concat(_interpreter: &mut Interpreter, args: &[Value]) -> Result<Value, String> {
+    let mismatch = |val: &Value| {
+        format!(
+            "concat expects all strings or all lists, got {} and {}",
+            instance_of(&args[0]),
+            instance_of(val)
+        )
+    };
+    match &args[0] {
+        Value::String(_) => {
+            let mut joined = String::new();
+            for arg in args {
+                match arg {
+                    Value::String(s) => joined.push_str(s),
+                    val => return Err(mismatch(val)),
+                }
+            }
+            Ok(Value::String(joined))
+        }
+        Value::List(_) => {
+            let mut joined = Vec::new();
+            for arg in args {
+                match arg {
+                    Value::List(elements) => joined.extend(elements.borrow().iter().cloned()),
+                    val => return Err(mismatch(val)),
+                }
+            }
+            Ok(Value::List(Rc::new(RefCell::new(joined))))
+        }
+        val => Err(format!(
+            "concat expects strings or lists, got {}",
+            instance_of(val)
+        )),
+    }
+}
+
+/// The elements of the list argument, copied so the callback can change
+/// the list while it is walked.
+fn list_arg(name: &str, value: &Value) -> Result<Vec<Value>, String> {
+    match value {
+        Value::List(elements) => Ok(elements.borrow().clone()),
+        val => Err(format!("{} expects a list, got {}", name, instance_of(val))),
+    }
+}
+
+/// A new list holding `f(x)` for every `x` in the list.
+fn map(interpreter: &mut Interpreter, args: &[Value]) -> Result<Value, String> {
+    let mapped = list_arg("map", &args[0])?
+        .into_iter()
+        .map(|element| interpreter.call_back(args[1].clone(), vec![element]))
+        .collect::<Result<_, _>>()?;
+    Ok(Value::List(Rc::new(RefCell::new(mapped))))
+}
+
+/// A new list of the elements for which `f` returns a truthy value.
+fn filter(interpreter: &mut Interpreter, args: &[Value]) -> Result<Value, String> {
+    let mut kept = Vec::new();
+    for element in list_arg("filter", &args[0])? {
+        let keep = interpreter.call_back(args[1].clone(), vec![element.clone()])?;
+        if Interpreter::is_truthy(&keep) {
+            kept.push(element);
+        }
+    }
+    Ok(Value::List(Rc::new(RefCell::new(kept))))
+}
+
+/// Folds the list from the left, starting from `init`: each step calls
+/// `f(accumulated, element)`.
+fn reduce(interpreter: &mut Interpreter, args: &[Value]) -> Result<Value, String> {
+    let mut accumulated = args[2].clone();
+    for element in list_arg("reduce", &args[0])? {
+        accumulated = interpreter.call_back(args[1].clone(), vec![accumulated, element])?;
+    }
+    Ok(accumulated)
+}
+
+/// A new list with the elements in ascending order, for a list of numbers
+/// or a list of strings. Equal elements keep their order.
+///
+/// NaN compares as greater than every other number and equal to itself,
+/// so all NaNs end up last, in the order they came in, whatever their
+/// sign bit. Division by zero is an error, so a script only gets a NaN
+/// from infinities: a product that overflows to `inf`, then `inf - inf`.
+fn sort(_interpreter: &mut Interpreter, args: &[Value]) -> Result<Value, String> {
+    let mut elements = list_arg("sort", &args[0])?;
+    if let Some(first) = elements.first() {
+        let kind = instance_of(first);
+        if !matches!(kind, LoxType::Number | LoxType::String) {
+            return Err(format!("sort can't order values of type {}", kind));
+        }
+        if let Some(other) = elements.iter().find(|val| instance_of(val) != kind) {
+            return Err(format!(
+                "sort can't order {} and {} together",
+                kind,
+                instance_of(other)
+            ));
+        }
+    }
+    elements.sort_by(|a, b| match (a, b) {
+        (Value::Number(a), Value::Number(b)) => a
+            .partial_cmp(b)
+            .unwrap_or_else(|| a.is_nan().cmp(&b.is_nan())),
+        (Value::String(a), Value::String(b)) => a.cmp(b),
+        _ => Ordering::Equal,
+    });
+    Ok(Value::List(Rc::new(RefCell::new(elements))))
+}
+
+/// The two numbers `name` was called with; `b` must not be zero.
+fn divide_args(name: &str, args: &[Value]) -> Result<(f64, f64), String> {
+    match (&args[0], &args[1]) {
+        (Value::Number(_), Value::Number(b)) if *b == 0.0 => {
+            Err(format!("ZeroDivisionError: {} by zero", name))
+        }
+        (Value::Number(a), Value::Number(b)) => Ok((*a, *b)),
+        (Value::Number(_), val) | (val, _) => Err(format!(
+            "{} expects numbers, got {}",
+            name,
+            instance_of(val)
+        )),
+    }
+}
+
+/// Division rounded toward negative infinity, so `floordiv(-7, 2)` is -4.
+fn floordiv(_interpreter: &mut Interpreter, args: &[Value]) -> Result<Value, String> {
+    let (a, b) = divide_args("floordiv", args)?;
+    Ok(Value::Number((a / b).floor()))
+}
+
+/// The remainder that goes with `floordiv`. It takes the sign of `b`, so
+/// `floormod(-7, 3)` is 2, as in Python.
+fn floormod(_interpreter: &mut Interpreter, args: &[Value]) -> Result<Value, String> {
+    let (a, b) = divide_args("floormod", args)?;
+    let r = a % b;
+    Ok(Value::Number(if r != 0.0 && (r < 0.0) != (b < 0.0) {
+        r + b
+    } else {
+        r
+    }))
+}
+
+fn max(_interpreter: &mut Interpreter, args: &[Value]) -> Result<Value, String> {
+    extreme("max", args, |n, best| n > best)
+}
+
+fn min(_interpreter: &mut Interpreter, args: &[Value]) -> Result<Value, String> {
+    extreme("min", args, |n, best| n < best)
+}
+
+/// Backs `min` and `max`, which take either numbers, as in `max(1, 2)`,
+/// or a single list of numbers, as in `max([1, 2])`.
+fn extreme(name: &str, args: &[Value], better: fn(f64, f64) -> bool) -> Result<Value, String> {
+    let list;
+    let values = match args {
+        [Value::List(elements)] => {
+            list = elements.borrow();
+            if list.is_empty() {
+                return Err(format!("{} expects a non-empty list", name));
+            }
+            &list[..]
+        }
+        _ => args,
+    };
+    let mut best = f64::NAN;
+    for (i, value) in values.iter().enumerate() {
+        match value {
+            Value::Number(n) if i == 0 || better(*n, best) => best = *n,
+            Value::Number(_) => {}
+            val => {
+                return Err(format!(
+                    "{} expects numbers, got {}",
+                    name,
+                    instance_of(val)
+                ))
+            }
+        }
+    }
+    Ok(Value::Number(best))
+}
+
+/// Roughly how many bytes `x` takes up: the value itself plus the buffer
+/// it owns. Like Python's `sys.getsizeof` it is shallow, so a list counts
+/// its element slots but not what the elements point to.
+fn sizeof(_interpreter: &mut Interpreter, args: &[Value]) -> Result<Value, String> {
+    let slot = std::mem::size_of::<Value>();
+    let owned = match &args[0] {
+        Value::String(s) => s.capacity(),
+        Value::Bytes(bytes) => bytes.capacity(),
+        Value::List(elements) => elements.borrow().capacity() * slot,
+        Value::Map(map) => map.borrow().len() * 2 * slot,
+        _ => 0,
+    };
+    Ok(Value::Number((slot + owned) as f64))
+}
+
+/// Runs Lox code from a string in the global scope; see
+/// `Interpreter::eval_source`.
+fn eval(interpreter: &mut Interpreter, args: &[Value]) -> Result<Value, String> {
+    match &args[0] {
+        Value::String(source) => interpreter.eval_source(source).map_err(|err| err.message),
+        val => Err(format!("eval expects a string, got {}", instance_of(val))),
+    }
+}
+
+/// Lists the names declared at the top level of the script, in
+/// declaration order, no matter which scope it's called from.
+fn globals(interpreter: &mut Interpreter, _args: &[Value]) -> Result<Value, String> {
+    let names = interpreter
+        .globals
+        .borrow()
+        .names()
+        .iter()
+        .map(|name| Value::String(name.clone()))
+        .collect();
+    Ok(Value::List(Rc::new(RefCell::new(names))))
+}
+
+/// Reads one line from the interpreter's input without the line ending,
+/// or `nil` once the input is exhausted.
+fn read_line(interpreter: &mut Interpreter, _args: &[Value]) -> Result<Value, String> {
+    let mut line = String::new();
+    match interpreter.input.read_line(&mut line) {
+        Ok(0) => Ok(Value::Nil),
+        Ok(_) => {
+            if line.ends_with('\n') {
+                line.pop();
+                if line.ends_with('\r') {
+                    line.pop();
+                }
+            }
+            Ok(Value::String(line))
+        }
+        Err(err) => Err(format!("read_line failed: {}", err)),
+    }
+}
+
+/// Raises a runtime error at the call with `msg` as its message. Anything
+/// other than a string is shown the way `print` would show it.
+fn panic(interpreter: &mut Interpreter, args: &[Value]) -> Result<Value, String> {
+    Err(interpreter.format_val(&args[0]))
+}
+
+/// Replaces each `{}` in the format string with the next argument, shown
+/// the way `print` would show it. `{{` and `}}` stand for literal braces.
+fn format(interpreter: &mut Interpreter, args: &[Value]) -> Result<Value, String> {
+    interpolate(interpreter, "format", args).map(Value::String)
+}
+
+/// Prints `format(fmt, ...)` followed by a newline, like `print`.
+fn printf(interpreter: &mut Interpreter, args: &[Value]) -> Result<Value, String> {
+    let text = interpolate(interpreter, "printf", args)?;
+    writeln!(interpreter.output, "{}", text)
+        .map_err(|err| format!("Failed to write output: {}", err))?;
+    Ok(Value::Nil)
+}
+
+fn interpolate(interpreter: &Interpreter, name: &str, args: &[Value]) -> Result<String, String> {
+    let Value::String(fmt) = &args[0] else {
+        return Err(format!(
+            "{} expects a format string, got {}",
+            name,
+            instance_of(&args[0])
+        ));
+    };
+    let values = &args[1..];
+    let mut out = String::new();
+    let mut placeholders = 0;
+    let mut chars = fmt.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            ('{', Some('{')) | ('}', Some('}')) => {
+                chars.next();
+                out.push(c);
+            }
+            ('{', Some('}')) => {
+                chars.next();
+                if let Some(val) = values.get(placeholders) {
+                    out.push_str(&interpreter.format_val(val));
+                }
+                placeholders += 1;
+            }
+            ('{', _) | ('}', _) => {
+                return Err(format!(
+                    "{} found an unmatched '{}', write '{}{}' for a literal brace",
+                    name, c, c, c
+                ))
+            }
+            _ => out.push(c),
+        }
+    }
+    if placeholders != values.len() {
+        return Err(format!(
+            "{} has {} placeholder(s) but was given {} argument(s)",
+            name,
+            placeholders,
+            values.len()
+        ));
+    }
+    Ok(out)
+}
+
+/// Fails with both values spelled out when they aren't `==`.
+fn assert_eq(_interpreter: &mut Interpreter, args: &[Value]) -> Result<Value, String> {
+    if Interpreter::equals(&args[0], &args[1]) {
+        Ok(Value::Nil)
+    } else {
+        Err(format!("assertion failed: {} != {}", args[0], args[1]))
+    }
+}
+
+fn random(interpreter: &mut Interpreter, _args: &[Value]) -> Result<Value, String> {
+    Ok(Value::Number(interpreter.rng.next_f64()))
+}
+
+/// `range(start, end)` counts up by one; `range(start, end, step)` by
+/// `step`, which may be negative but not zero.
+fn range(_interpreter: &mut Interpreter, args: &[Value]) -> Result<Value, String> {
+    let mut bounds = [0.0, 0.0, 1.0];
+    for (bound, arg) in bounds.iter_mut().zip(args) {
+        match arg {
+            Value::Number(n) => *bound = *n,
+            val => return Err(format!("range expects numbers, got {}", instance_of(val))),
+        }
+    }
+    let [start, end, step] = bounds;
+    if step == 0.0 {
+        return Err("range step can't be zero".to_string());
+    }
+    Ok(Value::Range(Range { start, end, step }))
+}
+
+/// Restarts `random` from `seed`; the same seed gives the same sequence.
+fn srand(interpreter: &mut Interpreter, args: &[Value]) -> Result<Value, String> {
+    match &args[0] {
+        Value::Number(seed) => {
+            interpreter.rng = Rng::new(seed.to_bits());
+            Ok(Value::Nil)
+        }
+        val => Err(format!("srand expects a number, got {}", instance_of(val))),
+    }
+}
+
+/// Calls `f` with no arguments and fails unless that raises a runtime
+/// error. The error itself is swallowed, so the script carries on.
+fn assert_throws(interpreter: &mut Interpreter, args: &[Value]) -> Result<Value, String> {
+    let arity = match &args[0] {
+        Value::LoxFunction(fun) => fun.arity(),
+        Value::NativeFunction(native) | Value::BoundMethod(_, native) => native.arity,
+        val => {
+            return Err(format!(
+                "assert_throws expects a function, got {}",
+                instance_of(val)
+            ))
+        }
+    };
+    // Otherwise the arity error itself would count as the throw.
+    if !arity.accepts(0) {
+        return Err(format!(
+            "assert_throws expects a function taking no arguments, it takes {}",
+            arity
+        ));
+    }
+    match interpreter.call_value(args[0].clone(), Vec::new()) {
+        Ok(val) => Err(format!(
+            "assertion failed: expected an error, but the call returned {}",
+            val
+        )),
+        Err(_) => Ok(Value::Nil),
+    }
+}
+
+fn clock(interpreter: &mut Interpreter, _args: &[Value]) -> Result<Value, String> {
+    Ok(Value::Number(interpreter.clock.now()))
+}
+
+/// Calls `f` with no arguments `n` times and returns the average time a
+/// call took, in milliseconds, on the same monotonic clock as
+/// `clock_millis`. An error from `f` ends the run and is passed on.
+fn benchmark(interpreter: &mut Interpreter, args: &[Value]) -> Result<Value, String> {
+    let runs = args[1]
+        .as_index()
+        .map_err(|message| format!("benchmark run count: {}", message))?;
+    if runs == 0 {
+        return Err("benchmark needs at least one run".to_string());
+    }
+    let start = interpreter.clock.millis();
+    for _ in 0..runs {
+        interpreter.call_back(args[0].clone(), Vec::new())?;
+    }
+    let elapsed = interpreter.clock.millis() - start;
+    Ok(Value::Number(elapsed / runs as f64))
+}
+
+fn clock_millis(interpreter: &mut Interpreter, _args: &[Value]) -> Result<Value, String> {
+    Ok(Value::Number(interpreter.clock.millis()))
+}
+
+fn sleep(interpreter: &mut Interpreter, args: &[Value]) -> Result<Value, String> {
+    match &args[0] {
+        Value::Number(seconds) if *seconds >= 0.0 && seconds.is_finite() => {
+            interpreter.clock.sleep(*seconds);
+            Ok(Value::Nil)
+        }
+        Value::Number(seconds) => Err(format!(
+            "sleep expects a non-negative number of seconds, got {}",
+            seconds
+        )),
+        val => Err(format!(
+            "sleep expects a number of seconds, got {}",
+            instance_of(val)
+        )),
+    }
+}
+
+/// The UTF-8 encoding of a string.
+fn bytes_of(_interpreter: &mut Interpreter, args: &[Value]) -> Result<Value, String> {
+    match &args[0] {
+        Value::String(s) => Ok(Value::Bytes(s.as_bytes().to_vec())),
+        val => Err(format!(
+            "bytes_of expects a string, got {}",
+            instance_of(val)
+        )),
+    }
+}
+
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Standard, padded base64.
+fn to_base64(_interpreter: &mut Interpreter, args: &[Value]) -> Result<Value, String> {
+    let bytes = match &args[0] {
+        Value::Bytes(bytes) => bytes,
+        val => return Err(format!("to_base64 expects bytes, got {}", instance_of(val))),
+    };
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, byte)| n | (*byte as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                let index = (n >> (18 - 6 * i)) & 0x3f;
+                encoded.push(BASE64_ALPHABET[index as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    Ok(Value::String(encoded))
+}
+
+fn from_base64(_interpreter: &mut Interpreter, args: &[Value]) -> Result<Value, String> {
+    let encoded = match &args[0] {
+        Value::String(s) => s,
+        val => {
+            return Err(format!(
+                "from_base64 expects a string, got {}",
+                instance_of(val)
+            ))
+        }
+    };
+    let invalid = || format!("from_base64 got invalid base64 '{}'", encoded);
+    let input = encoded.as_bytes();
+    if input.len() % 4 != 0 {
+        return Err(invalid());
+    }
+    let mut bytes = Vec::with_capacity(input.len() / 4 * 3);
+    for (chunk_index, chunk) in input.chunks(4).enumerate() {
+        let last = chunk_index == input.len() / 4 - 1;
+        let padding = chunk.iter().rev().take_while(|&&c| c == b'=').count();
+        if padding > 2 || (padding > 0 && !last) {
+            return Err(invalid());
+        }
+        let mut n = 0u32;
+        for (i, c) in chunk[..4 - padding].iter().enumerate() {
+            let value = BASE64_ALPHABET
+                .iter()
+                .position(|a| a == c)
+                .ok_or_else(invalid)?;
+            n |= (value as u32) << (18 - 6 * i);
+        }
+        for i in 0..3 - padding {
+            bytes.push((n >> (16 - 8 * i)) as u8);
+        }
+    }
+    Ok(Value::Bytes(bytes))
+}
+
+fn is_bool(_interpreter: &mut Interpreter, args: &[Value]) -> Result<Value, String> {
+    Ok(Value::Boolean(instance_of(&args[0]) == LoxType::Boolean))
+}
+
+fn is_nil(_interpreter: &mut Interpreter, args: &[Value]) -> Result<Value, String> {
+    Ok(Value::Boolean(instance_of(&args[0]) == LoxType::Nil))
+}
+
+fn is_number(_interpreter: &mut Interpreter, args: &[Value]) -> Result<Value, String> {
+    Ok(Value::Boolean(instance_of(&args[0]) == LoxType::Number))
+}
+
+fn is_string(_interpreter: &mut Interpreter, args: &[Value]) -> Result<Value, String> {
+    Ok(Value::Boolean(instance_of(&args[0]) == LoxType::String))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{interpreter, run, run_in};
+    use std::io::Cursor;
+
+    #[test]
+    fn sort_puts_nan_last() {
+        let (mut interpreter, _) = interpreter();
+        let numbers = [3.0, f64::NAN, 1.0, f64::NEG_INFINITY, -f64::NAN, 2.0];
+        let list = Value::List(Rc::new(RefCell::new(
+            numbers.into_iter().map(Value::Number).collect(),
+        )));
+        let Value::List(sorted) = sort(&mut interpreter, &[list]).unwrap() else {
+            panic!("sort didn't return a list");
+        };
+        let sorted: Vec<f64> = sorted
+            .borrow()
+            .iter()
+            .map(|val| match val {
+                Value::Number(n) => *n,
+                val => panic!("{} in the sorted list", instance_of(val)),
+            })
+            .collect();
+        assert_eq!(sorted[..4], [f64::NEG_INFINITY, 1.0, 2.0, 3.0]);
+        assert!(sorted[4].is_nan() && sorted[4].is_sign_positive());
+        assert!(sorted[5].is_nan() && sorted[5].is_sign_negative());
+    }
+
+    #[test]
+    fn sort_puts_a_nan_from_a_script_last() {
+        let output = run("
+            var big = 1;
+            for (var i = 0; i < 309; i = i + 1) big = big * 10;
+            print sort([3, big - big, 1, -big, 2]);");
+        assert_eq!(output.unwrap(), "[-inf, 1, 2, 3, nan]\n");
+    }
+
+    #[test]
+    fn contains_finds_substrings() {
+        let output = run("print contains(\"hello\", \"ell\"); print contains(\"hello\", \"xyz\");");
+        assert_eq!(output.unwrap(), "true\nfalse\n");
+    }
+
+    #[test]
+    fn contains_finds_list_elements() {
+        let output = run("print contains([1, \"a\", [2]], [2]); print contains([1, 2], 3);");
+        assert_eq!(output.unwrap(), "true\nfalse\n");
+    }
+
+    #[test]
+    fn contains_rejects_other_types() {
+        let err = run("contains(\"abc\", 1);").unwrap_err();
+        assert_eq!(
+            err,
+            "contains can only look for a string in a string, got number"
+        );
+        let err = run("contains(12, 1);").unwrap_err();
+        assert_eq!(err, "contains expects a string or a list, got number");
+    }
+
+    #[test]
+    fn read_line_reads_injected_input() {
+        let (mut interpreter, output) = interpreter();
+        interpreter.input = Box::new(Cursor::new("first\nsecond\n"));
+        run_in(
+            &mut interpreter,
+            "print read_line(); print read_line(); print read_line();",
+        )
+        .unwrap();
+        assert_eq!(output.contents(), "first\nsecond\nnil\n");
+    }
+
+    #[test]
+    fn sleep_advances_a_virtual_clock() {
+        let (mut interpreter, output) = interpreter();
+        interpreter.clock = Box::new(VirtualClock::default());
+        run_in(&mut interpreter, "sleep(2.5); print clock();").unwrap();
+        assert_eq!(output.contents(), "2.5\n");
+    }
+
+    #[test]
+    fn sleep_rejects_a_non_number() {
+        let err = run("sleep(\"x\");").unwrap_err();
+        assert_eq!(err, "sleep expects a number of seconds, got string");
+    }
+
+    #[test]
+    fn globals_lists_the_script_globals() {
+        let output = run("var a = 1; var b = 2; print globals();");
+        assert_eq!(output.unwrap(), "[a, b]\n");
+    }
+
+    #[test]
+    fn assert_eq_shows_both_values_when_they_differ() {
+        assert_eq!(
+            run("assert_eq([1, 2], [1, 2]); print \"ok\";").unwrap(),
+            "ok\n"
+        );
+        let err = run("assert_eq(3, 4);").unwrap_err();
+        assert_eq!(err, "assertion failed: 3 != 4");
+    }
+
+    #[test]
+    fn strings_round_trip_through_base64() {
+        let output = run("
+            var encoded = to_base64(bytes_of(\"héllo\"));
+            print encoded;
+            print from_base64(encoded) == bytes_of(\"héllo\");
+            print from_base64(encoded);");
+        assert_eq!(
+            output.unwrap(),
+            "aMOpbGxv\ntrue\n<6 bytes: 68 c3 a9 6c 6c 6f>\n"
+        );
+    }
+
+    #[test]
+    fn type_predicates_match_only_their_type() {
+        let output = run("
+            var values = [1, \"s\", true, nil];
+            for (v in values) print [is_number(v), is_string(v), is_bool(v), is_nil(v)];");
+        assert_eq!(
+            output.unwrap(),
+            "[true, false, false, false]\n\
+             [false, true, false, false]\n\
+             [false, false, true, false]\n\
+             [false, false, false, true]\n"
+        );
+    }
+
+    #[test]
+    fn map_keys_keep_insertion_order() {
+        let source = "
+            var m = new_map();
+            m.set(\"b\", 1); m.set(\"a\", 2); m.set(\"c\", 3); m.set(\"b\", 4);
+            print m.keys();";
+        for _ in 0..3 {
+            assert_eq!(run(source).unwrap(), "[b, a, c]\n");
+        }
+    }
+
+    #[test]
+    fn case_conversion_handles_non_ascii() {
+        let output = run("print upper(\"abc\"); print lower(\"ÄÖ\"); print upper(\"straße\");");
+        assert_eq!(output.unwrap(), "ABC\näö\nSTRASSE\n");
+    }
+
+    #[test]
+    fn trim_and_split() {
+        let output = run("
+            print trim(\"  a b  \") + \"|\";
+            var parts = split(\"a,b,c\", \",\");
+            print parts; print parts[2];");
+        assert_eq!(output.unwrap(), "a b|\n[a, b, c]\nc\n");
+    }
+
+    #[test]
+    fn concat_joins_strings_or_lists() {
+        let output = run("print concat(\"a\", \"b\", \"c\"); print concat([1], [2, 3], []);");
+        assert_eq!(output.unwrap(), "abc\n[1, 2, 3]\n");
+    }
+
+    #[test]
+    fn a_seeded_random_sequence_repeats() {
+        let source = "srand(42); var a = random(); var b = random(); print [a, b];";
+        let first = run(source).unwrap();
+        assert_eq!(first, run(source).unwrap());
+        assert_eq!(first, "[0.08215683177501132, 0.49379796880431914]\n");
+    }
+
+    #[test]
+    fn min_and_max_take_a_list() {
+        let output = run("print max([3, 1, 2]); print min([3, 1, 2]); print max(1, 5);");
+        assert_eq!(output.unwrap(), "3\n1\n5\n");
+        assert_eq!(run("max([]);").unwrap_err(), "max expects a non-empty list");
+    }
+
+    #[test]
+    fn sizeof_counts_at_least_the_content() {
+        let output = run("print sizeof(\"abcd\") >= 4; print sizeof([1, 2]) > sizeof([]);");
+        assert_eq!(output.unwrap(), "true\ntrue\n");
+    }
+
+    #[test]
+    fn assert_throws_needs_an_error() {
+        let source = "fun boom() { return 1 / 0; } fun fine() { return 1; }";
+        assert_eq!(
+            run(&format!("{} assert_throws(boom); print \"ok\";", source)).unwrap(),
+            "ok\n"
+        );
+        let err = run(&format!("{} assert_throws(fine);", source)).unwrap_err();
+        assert_eq!(
+            err,
+            "assertion failed: expected an error, but the call returned 1"
+        );
+    }
+
+    #[test]
+    fn map_filter_and_reduce_take_callbacks() {
+        let output = run("
+            fun double(x) { return x * 2; }
+            fun add(a, b) { return a + b; }
+            fun odd(x) { return floormod(x, 2) == 1; }
+            print map([1, 2, 3], double);
+            print filter([1, 2, 3], odd);
+            print reduce([1, 2, 3], add, 0);");
+        assert_eq!(output.unwrap(), "[2, 4, 6]\n[1, 3]\n6\n");
+    }
+
+    #[test]
+    fn floordiv_and_floormod_round_towards_negative_infinity() {
+        let output = run("
+            print floordiv(-7, 2); print floormod(-7, 2);
+            print floordiv(7, -2); print floormod(7, -2);");
+        assert_eq!(output.unwrap(), "-4\n1\n-4\n-1\n");
+        assert_eq!(
+            run("floormod(7, 0);").unwrap_err(),
+            "ZeroDivisionError: floormod by zero"
+        );
+    }
+
+    #[test]
+    fn eval_returns_the_value_of_its_source() {
+        let output = run("print eval(\"1 + 2\"); print eval(\"var q = 5; q * 2\");");
+        assert_eq!(output.unwrap(), "3\n10\n");
+    }
+
+    #[test]
+    fn panic_raises_its_message() {
+        assert!(run("panic(\"boom\");").unwrap_err().contains("boom"));
+    }
+
+    #[test]
+    fn format_fills_placeholders_in_order() {
+        let output = run("print format(\"{} + {} = {}\", 1, 2, 3);");
+        assert_eq!(output.unwrap(), "1 + 2 = 3\n");
+        let err = run("format(\"{}\", \"a\", \"b\");").unwrap_err();
+        assert_eq!(
+            err,
+            "format has 1 placeholder(s) but was given 2 argument(s)"
+        );
+    }
+
+    #[test]
+    fn the_sandbox_blocks_host_natives() {
+        let (mut interpreter, _) = interpreter();
+        interpreter.enable_sandbox();
+        let err = run_in(&mut interpreter, "read_line();").unwrap_err();
+        assert_eq!(err, "read_line: operation not permitted in sandbox");
+    }
+
+    #[test]
+    fn benchmark_returns_a_non_negative_time() {
+        let (mut interpreter, output) = interpreter();
+        interpreter.clock = Box::new(VirtualClock::default());
+        run_in(&mut interpreter, "fun f() {} print benchmark(f, 10) >= 0;").unwrap();
+        assert_eq!(output.contents(), "true\n");
+        assert_eq!(
+            run("fun f() {} print benchmark(f, 10) >= 0;").unwrap(),
+            "true\n"
+        );
+    }
+
+    #[test]
+    fn clock_millis_never_goes_backwards() {
+        let output = run("var a = clock_millis(); var b = clock_millis(); print b >= a;");
+        assert_eq!(output.unwrap(), "true\n");
+        let (mut interpreter, output) = interpreter();
+        interpreter.clock = Box::new(VirtualClock::default());
+        run_in(&mut interpreter, "sleep(0.25); print clock_millis();").unwrap();
+        assert_eq!(output.contents(), "250\n");
+    }
+}
@@ -0,0 +1,379 @@
+use crate::expr::{
+    BinaryOp, BinaryOpType, Expr, ExprVisitor, FunDecl, Literal, LogicalOp, SourceLocation, Stmt,
+    StmtVisitor, Symbol, UnaryOp,
+};
+use crate::tree_interpreter::{Interpreter, Value};
+
+/// Replaces subexpressions made only of literals with the literal they
+/// evaluate to, so `2 + 3 * 4` runs as `14`. An operation that fails at
+/// runtime, such as `1 / 0`, is left in place to fail where it is.
+pub fn fold_constants(program: &[Stmt]) -> Vec<Stmt> {
+    ConstantFolder::default().fold_body(program)
+}
+
+/// Evaluates the foldable operations with a plain interpreter, so folding
+/// can't disagree with what running the expression would have done.
+#[derive(Default)]
+struct ConstantFolder {
+    interpreter: Interpreter,
+}
+
+impl ConstantFolder {
+    fn fold(&mut self, expr: &Expr) -> Expr {
+        expr.accept(self)
+    }
+
+    fn fold_all(&mut self, exprs: &[Expr]) -> Vec<Expr> {
+        exprs.iter().map(|expr| self.fold(expr)).collect()
+    }
+
+    fn fold_body(&mut self, stmts: &[Stmt]) -> Vec<Stmt> {
+        stmts.iter().map(|stmt| stmt.accept(self)).collect()
+    }
+
+    fn fold_stmt(&mut self, stmt: &Stmt) -> Box<Stmt> {
+        Box::new(stmt.accept(self))
+    }
+
+    /// Evaluates `expr`, whose operands are all literals, keeping it as it
+    /// is if that fails.
+    fn evaluate(&mut self, expr: Expr) -> Expr {
+        match expr.accept(&mut self.interpreter) {
+            Ok(Value::Number(n)) => Expr::Literal(Literal::Number(n, None)),
+            Ok(Value::String(s)) => Expr::Literal(Literal::String(s)),
+            Ok(Value::Boolean(true)) => Expr::Literal(Literal::True),
+            Ok(Value::Boolean(false)) => Expr::Literal(Literal::False),
+            Ok(Value::Nil) => Expr::Literal(Literal::Nil),
+            _ => expr,
+        }
+    }
+}
+
+/// The literal `expr` amounts to, looking through parentheses.
+fn literal(expr: &Expr) -> Option<&Literal> {
+    match expr {
+        Expr::Literal(literal) => Some(literal),
+        Expr::Grouping(_, inner) => literal(inner),
+        _ => None,
+    }
+}
+
+impl ExprVisitor for ConstantFolder {
+    type Output = Expr;
+
+    fn visit_assign(&mut self, name: &Symbol, value: &Expr) -> Self::Output {
+        Expr::Assign(name.clone(), Box::new(self.fold(value)))
+    }
+
+    fn visit_assign_destructure(
+        &mut self,
+        loc: &SourceLocation,
+        names: &[Symbol],
+        value: &Expr,
+    ) -> Self::Output {
+        Expr::AssignDestructure(loc.clone(), names.to_vec(), Box::new(self.fold(value)))
+    }
+
+    fn visit_unary(&mut self, op: &UnaryOp, operand: &Expr) -> Self::Output {
+        let operand = self.fold(operand);
+        let folded = literal(&operand).is_some();
+        let expr = Expr::Unary(*op, Box::new(operand));
+        if folded {
+            self.evaluate(expr)
+        } else {
+            expr
+        }
+    }
+
+    fn visit_binary(&mut self, lhs: &Expr, op: &BinaryOp, rhs: &Expr) -> Self::Output {
+        let lhs = self.fold(lhs);
+        let rhs = self.fold(rhs);
+        let folded = match (literal(&lhs), op.op_type, literal(&rhs)) {
+            // Concatenating a number or a boolean depends on how the
+            // interpreter is set to format numbers, so only strings fold.
+            (Some(Literal::String(_)), BinaryOpType::Plus, Some(Literal::String(_))) => true,
+            (Some(Literal::String(_)), BinaryOpType::Plus, Some(_))
+            | (Some(_), BinaryOpType::Plus, Some(Literal::String(_))) => false,
+            (Some(_), _, Some(_)) => true,
+            _ => false,
+        };
+        let expr = Expr::Binary(Box::new(lhs), *op, Box::new(rhs));
+        if folded {
+            self.evaluate(expr)
+        } else {
+            expr
+        }
+    }
+
+    fn visit_call(&mut self, callee: &Expr, loc: &SourceLocation, args: &[Expr]) -> Self::Output {
+        Expr::Call(
+            Box::new(self.fold(callee)),
+            loc.clone(),
+            self.fold_all(args),
+        )
+    }
+
+    fn visit_get(&mut self, object: &Expr, name: &Symbol, nil_safe: bool) -> Self::Output {
+        Expr::Get(Box::new(self.fold(object)), name.clone(), nil_safe)
+    }
+
+    fn visit_index(&mut self, object: &Expr, loc: &SourceLocation, index: &Expr) -> Self::Output {
+        Expr::Index(
+            Box::new(self.fold(object)),
+            loc.clone(),
+            Box::new(self.fold(index)),
+        )
+    }
+
+    fn visit_set_index(
+        &mut self,
+        object: &Expr,
+        loc: &SourceLocation,
+        index: &Expr,
+        value: &Expr,
+    ) -> Self::Output {
+        Expr::SetIndex(
+            Box::new(self.fold(object)),
+            loc.clone(),
+            Box::new(self.fold(index)),
+            Box::new(self.fold(value)),
+        )
+    }
+
+    fn visit_list(&mut self, elements: &[Expr]) -> Self::Output {
+        Expr::List(self.fold_all(elements))
+    }
+
+    fn visit_comprehension(
+        &mut self,
+        element: &Expr,
+        loc: &SourceLocation,
+        name: &Symbol,
+        iterable: &Expr,
+    ) -> Self::Output {
+        Expr::Comprehension(
+            Box::new(self.fold(element)),
+            loc.clone(),
+            name.clone(),
+            Box::new(self.fold(iterable)),
+        )
+    }
+
+    /// The parentheses stay, so an error in the enclosing operation can
+    /// still point at them.
+    fn visit_grouping(&mut self, loc: &SourceLocation, expr: &Expr) -> Self::Output {
+        Expr::Grouping(loc.clone(), Box::new(self.fold(expr)))
+    }
+
+    fn visit_literal(&mut self, literal: &Literal) -> Self::Output {
+        Expr::Literal(literal.clone())
+    }
+
+    /// A literal left operand decides whether the right one is needed, so
+    /// `false and f()` folds to `false` and `true and x` to `x`.
+    fn visit_logical(&mut self, lhs: &Expr, op: &LogicalOp, rhs: &Expr) -> Self::Output {
+        let lhs = self.fold(lhs);
+        let rhs = self.fold(rhs);
+        let Some(left) = literal(&lhs) else {
+            return Expr::Logical(Box::new(lhs), op.clone(), Box::new(rhs));
+        };
+        let truthy = !matches!(left, Literal::False | Literal::Nil);
+        match (op, truthy) {
+            (LogicalOp::Or, true) | (LogicalOp::And, false) => Expr::Literal(left.clone()),
+            _ => rhs,
+        }
+    }
+
+    fn visit_set(&mut self, object: &Expr, name: &Symbol, value: &Expr) -> Self::Output {
+        Expr::Set(
+            Box::new(self.fold(object)),
+            name.clone(),
+            Box::new(self.fold(value)),
+        )
+    }
+
+    fn visit_super(&mut self, loc: &SourceLocation, method: &Symbol) -> Self::Output {
+        Expr::Super(loc.clone(), method.clone())
+    }
+
+    fn visit_this(&mut self, loc: &SourceLocation) -> Self::Output {
+        Expr::This(loc.clone())
+    }
+
+    fn visit_variable(&mut self, name: &Symbol) -> Self::Output {
+        Expr::Variable(name.clone())
+    }
+}
+
+impl StmtVisitor for ConstantFolder {
+    type Output = Stmt;
+
+    fn visit_expr_stmt(&mut self, expr: &Expr) -> Self::Output {
+        Stmt::Expr(self.fold(expr))
+    }
+
+    fn visit_fun_decl(&mut self, decl: &FunDecl) -> Self::Output {
+        Stmt::FunDecl(FunDecl {
+            name: decl.name.clone(),
+            params: decl.params.clone(),
+            rest: decl.rest.clone(),
+            body: self.fold_body(&decl.body),
+        })
+    }
+
+    fn visit_if(
+        &mut self,
+        condition: &Expr,
+        then_branch: &Stmt,
+        else_branch: Option<&Stmt>,
+    ) -> Self::Output {
+        Stmt::If(
+            self.fold(condition),
+            self.fold_stmt(then_branch),
+            else_branch.map(|stmt| self.fold_stmt(stmt)),
+        )
+    }
+
+    fn visit_print(&mut self, expr: &Expr) -> Self::Output {
+        Stmt::Print(self.fold(expr))
+    }
+
+    fn visit_eprint(&mut self, expr: &Expr) -> Self::Output {
+        Stmt::Eprint(self.fold(expr))
+    }
+
+    fn visit_debug(&mut self, expr: &Expr) -> Self::Output {
+        Stmt::Debug(self.fold(expr))
+    }
+
+    fn visit_assert(
+        &mut self,
+        loc: &SourceLocation,
+        condition: &Expr,
+        message: Option<&Expr>,
+    ) -> Self::Output {
+        Stmt::Assert(
+            loc.clone(),
+            self.fold(condition),
+            message.map(|expr| self.fold(expr)),
+        )
+    }
+
+    fn visit_var_decl(&mut self, name: &Symbol, initializer: Option<&Expr>) -> Self::Output {
+        Stmt::VarDecl(name.clone(), initializer.map(|expr| self.fold(expr)))
+    }
+
+    fn visit_var_destructure(
+        &mut self,
+        loc: &SourceLocation,
+        names: &[Symbol],
+        initializer: &Expr,
+    ) -> Self::Output {
+        Stmt::VarDestructure(loc.clone(), names.to_vec(), self.fold(initializer))
+    }
+
+    fn visit_const_decl(&mut self, name: &Symbol, initializer: &Expr) -> Self::Output {
+        Stmt::ConstDecl(name.clone(), self.fold(initializer))
+    }
+
+    fn visit_block(&mut self, stmts: &[Stmt]) -> Self::Output {
+        Stmt::Block(self.fold_body(stmts))
+    }
+
+    fn visit_empty(&mut self) -> Self::Output {
+        Stmt::Empty
+    }
+
+    fn visit_repeat(
+        &mut self,
+        loc: &SourceLocation,
+        label: Option<&Symbol>,
+        count: &Expr,
+        body: &Stmt,
+    ) -> Self::Output {
+        Stmt::Repeat(
+            loc.clone(),
+            label.cloned(),
+            self.fold(count),
+            self.fold_stmt(body),
+        )
+    }
+
+    fn visit_while(
+        &mut self,
+        label: Option<&Symbol>,
+        condition: &Expr,
+        body: &Stmt,
+        increment: Option<&Expr>,
+    ) -> Self::Output {
+        Stmt::While(
+            label.cloned(),
+            self.fold(condition),
+            self.fold_stmt(body),
+            increment.map(|expr| self.fold(expr)),
+        )
+    }
+
+    fn visit_for_in(
+        &mut self,
+        loc: &SourceLocation,
+        label: Option<&Symbol>,
+        name: &Symbol,
+        iterable: &Expr,
+        body: &Stmt,
+    ) -> Self::Output {
+        Stmt::ForIn(
+            loc.clone(),
+            label.cloned(),
+            name.clone(),
+            self.fold(iterable),
+            self.fold_stmt(body),
+        )
+    }
+
+    fn visit_do_while(
+        &mut self,
+        label: Option<&Symbol>,
+        body: &Stmt,
+        condition: &Expr,
+    ) -> Self::Output {
+        Stmt::DoWhile(label.cloned(), self.fold_stmt(body), self.fold(condition))
+    }
+
+    fn visit_break(&mut self, label: Option<&Symbol>) -> Self::Output {
+        Stmt::Break(label.cloned())
+    }
+
+    fn visit_continue(&mut self, label: Option<&Symbol>) -> Self::Output {
+        Stmt::Continue(label.cloned())
+    }
+
+    fn visit_return(&mut self, loc: &SourceLocation, value: Option<&Expr>) -> Self::Output {
+        Stmt::Return(loc.clone(), value.map(|expr| self.fold(expr)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast_printer::print_stmt;
+
+    fn folded(source: &str) -> Vec<String> {
+        let program = crate::parse(source.to_string()).unwrap();
+        fold_constants(&program).iter().map(print_stmt).collect()
+    }
+
+    #[test]
+    fn constant_expressions_are_folded() {
+        assert_eq!(folded("print 1 + 2 * 3;"), ["print 7;"]);
+        assert_eq!(
+            folded("var x = 2; print x * (3 + 4);"),
+            ["var x = 2;", "print x * (7);"]
+        );
+    }
+
+    #[test]
+    fn division_by_zero_is_left_for_run_time() {
+        assert_eq!(folded("print 1 / 0;"), ["print 1 / 0;"]);
+    }
+}
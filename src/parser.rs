@@ -1,11 +1,45 @@
-use crate::expr::{self, BinaryOp, Literal, Stmt, Symbol, UnaryOp, UnaryOpType};
-use crate::expr::{BinaryOpType, Expr};
+use crate::expr::{
+    BinaryOp, FunDecl, Literal, SourceLocation, Span, Stmt, Symbol, UnaryOp, UnaryOpType,
+};
+use crate::expr::{BinaryOpType, Expr, LogicalOp};
 use crate::scanner::{self, *};
+use std::fmt;
+
+/// Default nesting limit for expressions and statements, low enough that
+/// parsing can't exhaust the native stack. A nested grouping takes about
+/// 30 KB of it in a debug build, so this many fit in the 2 MB a spawned
+/// thread gets.
+pub const DEFAULT_MAX_DEPTH: usize = 32;
 
-#[derive(Default)]
 pub struct Parser {
     pub current: usize,
     pub tokens: Vec<Token>,
+    /// Deeper nesting is reported as `Error::TooDeeplyNested`.
+    pub max_depth: usize,
+    depth: usize,
+    errors: Vec<Error>,
+    /// Labels of the loops around the statement being parsed, innermost
+    /// last, so `break` and `continue` can be checked statically.
+    loops: Vec<Option<String>>,
+    /// Automatic semicolon insertion: a line break may end a statement.
+    asi: bool,
+    /// Whether a line break came right before each token, for `asi`.
+    newline_before: Vec<bool>,
+}
+
+impl Default for Parser {
+    fn default() -> Self {
+        Parser {
+            current: 0,
+            tokens: vec![],
+            max_depth: DEFAULT_MAX_DEPTH,
+            depth: 0,
+            errors: vec![],
+            loops: vec![],
+            asi: false,
+            newline_before: vec![],
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -21,139 +55,779 @@ pub enum Error {
         line: usize,
         col: i64,
     },
+    /// The left of an `=` isn't a variable or an index. Points at the `=`.
     InvalidAssignment {
+        /// What was there instead, such as "a literal".
+        target: &'static str,
+        line: usize,
+        col: i64,
+    },
+    TooDeeplyNested {
+        line: usize,
+        col: i64,
+    },
+    /// `break` or `continue` with no loop around it.
+    OutsideLoop {
+        keyword: String,
+        line: usize,
+        col: i64,
+    },
+    UndefinedLabel {
+        label: String,
+        line: usize,
+        col: i64,
+    },
+    /// `a < b < c`, which would compare a boolean with `c`. Points at the
+    /// second operator.
+    ChainedComparison {
+        first: String,
+        second: String,
         line: usize,
         col: i64,
     },
 }
 
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (line, col) = self.location();
+        write!(f, "{} at line {}, column {}.", self.message(), line, col)
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl Error {
+    /// The error text without its location.
+    pub fn message(&self) -> String {
+        match self {
+            Error::UnexpectedToken(token) => {
+                format!("Unexpected token {}", token.t_type.describe())
+            }
+            Error::TokenMissmatch {
+                expected,
+                found,
+                message,
+            } => {
+                let message = match message {
+                    Some(message) => message.trim_end_matches('.').to_string(),
+                    None => format!("Expected {}", expected.describe()),
+                };
+                if found.t_type == TokenType::Eof {
+                    format!("{}, found end of input", message)
+                } else {
+                    format!(
+                        "{}, found '{}'",
+                        message,
+                        String::from_utf8_lossy(&found.lexeme)
+                    )
+                }
+            }
+            Error::ExpectedExpression { token_type, .. } => {
+                format!("Expected expression, found {}", token_type.describe())
+            }
+            Error::InvalidAssignment { target, .. } => {
+                format!("Invalid assignment target, cannot assign to {}", target)
+            }
+            Error::TooDeeplyNested { .. } => "Expression too deeply nested".to_string(),
+            Error::OutsideLoop { keyword, .. } => format!("'{}' outside of a loop", keyword),
+            Error::UndefinedLabel { label, .. } => format!("No enclosing loop labeled '{}'", label),
+            Error::ChainedComparison { first, second, .. } => format!(
+                "Comparisons can't be chained, write 'a {} b and b {} c' instead",
+                first, second
+            ),
+        }
+    }
+
+    /// Line and column the error points at.
+    pub fn location(&self) -> (usize, i64) {
+        match self {
+            Error::UnexpectedToken(token) => (token.line, token.col),
+            Error::TokenMissmatch { found, .. } => (found.line, found.col),
+            Error::ExpectedExpression { line, col, .. }
+            | Error::InvalidAssignment { line, col, .. }
+            | Error::TooDeeplyNested { line, col }
+            | Error::OutsideLoop { line, col, .. }
+            | Error::UndefinedLabel { line, col, .. }
+            | Error::ChainedComparison { line, col, .. } => (*line, *col),
+        }
+    }
+}
+
 impl Parser {
-    pub fn parse(&mut self) -> Result<Vec<Stmt>, Error> {
+    pub fn new(tokens: Vec<Token>) -> Self {
+        Parser::with_asi(tokens, false)
+    }
+
+    /// With `asi`, a line break, a `}` or the end of the input may end a
+    /// statement. The scanner only emits `Newline` tokens when asked to;
+    /// they are taken out of the stream here, leaving a note on the token
+    /// that follows.
+    pub fn with_asi(tokens: Vec<Token>, asi: bool) -> Self {
+        let mut kept = Vec::with_capacity(tokens.len());
+        let mut newline_before = Vec::with_capacity(tokens.len());
+        let mut newline = false;
+        for token in tokens {
+            if token.t_type == TokenType::Newline {
+                newline = true;
+            } else {
+                kept.push(token);
+                newline_before.push(std::mem::take(&mut newline));
+            }
+        }
+        Parser {
+            tokens: kept,
+            asi,
+            newline_before,
+            ..Default::default()
+        }
+    }
+
+    /// Parses the whole program. A malformed statement doesn't stop the
+    /// parse: the error is recorded, the parser skips ahead to the next
+    /// statement and every error found is returned together.
+    ///
+    /// A program wrapped in `{ ... }` as a whole is a single block
+    /// statement, so its declarations stay local to it.
+    pub fn parse(&mut self) -> Result<Vec<Stmt>, Vec<Error>> {
         // self.tokens = tokens;
         let mut statements = vec![];
         while !self.is_at_end() {
-            statements.push(self.declaration()?);
+            if let Some(stmt) = self.recovering_declaration() {
+                statements.push(stmt);
+            }
         }
 
-        Ok(statements)
+        if self.errors.is_empty() {
+            Ok(statements)
+        } else {
+            Err(std::mem::take(&mut self.errors))
+        }
+    }
+
+    /// Parses the tokens as one expression, with nothing after it, not
+    /// even a `;`.
+    pub fn parse_expression(&mut self) -> Result<Expr, Error> {
+        let expr = self.expression()?;
+        if !self.is_at_end() {
+            return Err(Error::TokenMissmatch {
+                expected: TokenType::Eof,
+                found: self.peek().clone(),
+                message: Some("Expect end of input after the expression.".to_string()),
+            });
+        }
+        Ok(expr)
+    }
+
+    fn recovering_declaration(&mut self) -> Option<Stmt> {
+        match self.declaration() {
+            Ok(stmt) => Some(stmt),
+            Err(err) => {
+                self.errors.push(err);
+                self.synchronize();
+                None
+            }
+        }
     }
 
     fn declaration(&mut self) -> Result<Stmt, Error> {
+        if self.match_one(TokenType::Fun) {
+            return self.fun_declaration();
+        }
         if self.match_one(TokenType::Var) {
             return self.var_declaration();
         }
+        if self.match_one(TokenType::Const) {
+            return self.const_declaration();
+        }
         self.statement()
     }
 
+    fn fun_declaration(&mut self) -> Result<Stmt, Error> {
+        let name_token = self.consume(TokenType::Identifier, "Expect a function name")?;
+        self.consume(TokenType::LeftParen, "Expect '(' after function name")?;
+        let mut params = vec![];
+        let mut rest = None;
+        if !self.check(TokenType::RightParen) {
+            loop {
+                if self.match_one(TokenType::DotDotDot) {
+                    let param = self.consume(TokenType::Identifier, "Expect a parameter name")?;
+                    rest = Some(Self::symbol(&param));
+                    self.consume(
+                        TokenType::RightParen,
+                        "Expect ')' after the rest parameter, it must come last",
+                    )?;
+                    break;
+                }
+                let param = self.consume(TokenType::Identifier, "Expect a parameter name")?;
+                params.push(Self::symbol(&param));
+                if !self.match_one(TokenType::Comma) {
+                    break;
+                }
+            }
+        }
+        if rest.is_none() {
+            self.consume(TokenType::RightParen, "Expect ')' after parameters")?;
+        }
+        self.consume(TokenType::LeftBrace, "Expect '{' before function body")?;
+        // Loops outside the function can't be broken out of from inside it.
+        let loops = std::mem::take(&mut self.loops);
+        let body = self.block();
+        self.loops = loops;
+        let body = body?;
+        Ok(Stmt::FunDecl(FunDecl {
+            name: Self::symbol(&name_token),
+            params,
+            rest,
+            body,
+        }))
+    }
+
     fn var_declaration(&mut self) -> Result<Stmt, Error> {
+        if self.match_one(TokenType::LeftParen) {
+            let loc = Self::location(self.previous());
+            let names = self.name_list()?;
+            self.consume(
+                TokenType::Equal,
+                "Expect '=' after the names, destructuring needs a value",
+            )?;
+            let initializer = self.expression()?;
+            self.end_statement("Expect ';' after variable declaration")?;
+            return Ok(Stmt::VarDestructure(loc, names, initializer));
+        }
         let name_token = self.consume(TokenType::Identifier, "Expect a variable name")?;
 
         let mut initilizer = None;
         if self.match_one(TokenType::Equal) {
             initilizer = Some(self.expression()?);
         }
+        self.end_statement("Expect ';' after variable declaration")?;
+        let stmt = Stmt::VarDecl(Self::symbol(&name_token), initilizer);
+        Ok(stmt)
+    }
+
+    /// `a, b)`, the rest of a destructuring target after its `(`.
+    fn name_list(&mut self) -> Result<Vec<Symbol>, Error> {
+        let mut names = Vec::new();
+        loop {
+            let name = self.consume(TokenType::Identifier, "Expect a variable name")?;
+            names.push(Self::symbol(&name));
+            if !self.match_one(TokenType::Comma) {
+                break;
+            }
+        }
+        self.consume(TokenType::RightParen, "Expect ')' after the names")?;
+        Ok(names)
+    }
+
+    /// Whether the tokens ahead read `(a, b) =`, which can't be told apart
+    /// from a parenthesized expression until the `=`.
+    fn destructuring_ahead(&self) -> bool {
+        let at = |i: usize| self.tokens.get(self.current + i).map(|t| t.t_type);
+        if at(0) != Some(TokenType::LeftParen) {
+            return false;
+        }
+        let mut i = 1;
+        while at(i) == Some(TokenType::Identifier) {
+            match at(i + 1) {
+                Some(TokenType::Comma) => i += 2,
+                Some(TokenType::RightParen) => return at(i + 2) == Some(TokenType::Equal),
+                _ => return false,
+            }
+        }
+        false
+    }
+
+    fn location(token: &Token) -> SourceLocation {
+        SourceLocation {
+            line: token.line,
+            col: token.col,
+        }
+    }
+
+    fn const_declaration(&mut self) -> Result<Stmt, Error> {
+        let name_token = self.consume(TokenType::Identifier, "Expect a constant name")?;
         self.consume(
-            TokenType::Semicolon,
-            "Expect ';' after variable declaration",
+            TokenType::Equal,
+            "Expect '=' after constant name, constants must be initialized",
         )?;
-        let stmt = Stmt::VarDecl(
-            Symbol {
-                name: String::from_utf8(name_token.lexeme).unwrap(), // Token Identifier stored in lexeme
-                line: name_token.line,
-                col: -1,
-            },
-            initilizer,
-        );
-        Ok(stmt)
+        let initilizer = self.expression()?;
+        self.end_statement("Expect ';' after constant declaration")?;
+        Ok(Stmt::ConstDecl(Self::symbol(&name_token), initilizer))
     }
 
     fn statement(&mut self) -> Result<Stmt, Error> {
+        self.nested(Self::statement_inner)
+    }
+
+    fn statement_inner(&mut self) -> Result<Stmt, Error> {
+        if self.check(TokenType::Identifier) && self.check_next(TokenType::Colon) {
+            let label = Self::symbol(self.advance());
+            self.advance();
+            return self.labeled_loop(label);
+        }
+        if self.match_one(TokenType::If) {
+            return self.if_stmt();
+        }
         if self.match_one(TokenType::Print) {
             return self.print_stmt();
         }
+        if self.match_one(TokenType::Eprint) {
+            return self.eprint_stmt();
+        }
+        if self.match_one(TokenType::Debug) {
+            return self.debug_stmt();
+        }
+        if self.match_one(TokenType::Assert) {
+            return self.assert_stmt();
+        }
+        if self.match_one(TokenType::Return) {
+            return self.return_stmt();
+        }
+        if self.match_one(TokenType::Repeat) {
+            return self.repeat_stmt(None);
+        }
+        if self.match_one(TokenType::While) {
+            return self.while_stmt(None);
+        }
+        if self.match_one(TokenType::For) {
+            return self.for_stmt(None);
+        }
+        if self.match_one(TokenType::Do) {
+            return self.do_while_stmt(None);
+        }
+        if self.match_one(TokenType::Break) || self.match_one(TokenType::Continue) {
+            return self.loop_control_stmt();
+        }
+        if self.match_one(TokenType::With) {
+            return self.with_stmt();
+        }
+        if self.match_one(TokenType::LeftBrace) {
+            return Ok(Stmt::Block(self.block()?));
+        }
+        if self.match_one(TokenType::Semicolon) {
+            return Ok(Stmt::Empty);
+        }
         self.expression_stmt()
     }
 
+    fn if_stmt(&mut self) -> Result<Stmt, Error> {
+        self.consume(TokenType::LeftParen, "Expect '(' after 'if'.")?;
+        let condition = self.expression()?;
+        self.consume(TokenType::RightParen, "Expect ')' after if condition.")?;
+        let then_branch = Box::new(self.statement()?);
+        let else_branch = if self.match_one(TokenType::Else) {
+            Some(Box::new(self.statement()?))
+        } else {
+            None
+        };
+        Ok(Stmt::If(condition, then_branch, else_branch))
+    }
+
+    /// `with (var x = value) body` runs `body` with `x` bound, as a block
+    /// holding the declaration and then `body`. Leaving the block in any
+    /// way, `return` and `break` included, drops `x` with it.
+    fn with_stmt(&mut self) -> Result<Stmt, Error> {
+        self.consume(TokenType::LeftParen, "Expect '(' after 'with'.")?;
+        self.consume(TokenType::Var, "Expect 'var' after 'with ('.")?;
+        let name = Self::symbol(&self.consume(TokenType::Identifier, "Expect a variable name")?);
+        self.consume(
+            TokenType::Equal,
+            "Expect '=' after the name, 'with' needs a value",
+        )?;
+        let value = self.expression()?;
+        self.consume(TokenType::RightParen, "Expect ')' after the binding.")?;
+        let body = self.statement()?;
+        Ok(Stmt::Block(vec![Stmt::VarDecl(name, Some(value)), body]))
+    }
+
+    /// `label: while (...) ...`; only loops can be labeled.
+    fn labeled_loop(&mut self, label: Symbol) -> Result<Stmt, Error> {
+        if self.match_one(TokenType::Repeat) {
+            return self.repeat_stmt(Some(label));
+        }
+        if self.match_one(TokenType::While) {
+            return self.while_stmt(Some(label));
+        }
+        if self.match_one(TokenType::For) {
+            return self.for_stmt(Some(label));
+        }
+        if self.match_one(TokenType::Do) {
+            return self.do_while_stmt(Some(label));
+        }
+        Err(Error::TokenMissmatch {
+            expected: TokenType::While,
+            found: self.peek().clone(),
+            message: Some("Expect a loop after label.".to_string()),
+        })
+    }
+
+    /// Parses a loop body with `label` in scope for `break` and `continue`.
+    fn loop_body(&mut self, label: &Option<Symbol>) -> Result<Box<Stmt>, Error> {
+        self.loops
+            .push(label.as_ref().map(|label| label.name.clone()));
+        let body = self.statement();
+        self.loops.pop();
+        Ok(Box::new(body?))
+    }
+
+    fn repeat_stmt(&mut self, label: Option<Symbol>) -> Result<Stmt, Error> {
+        let keyword = self.previous().clone();
+        self.consume(TokenType::LeftParen, "Expect '(' after 'repeat'.")?;
+        let count = self.expression()?;
+        self.consume(TokenType::RightParen, "Expect ')' after repeat count.")?;
+        let body = self.loop_body(&label)?;
+        Ok(Stmt::Repeat(
+            SourceLocation {
+                line: keyword.line,
+                col: keyword.col,
+            },
+            label,
+            count,
+            body,
+        ))
+    }
+
+    fn while_stmt(&mut self, label: Option<Symbol>) -> Result<Stmt, Error> {
+        self.consume(TokenType::LeftParen, "Expect '(' after 'while'.")?;
+        let condition = self.expression()?;
+        self.consume(TokenType::RightParen, "Expect ')' after condition.")?;
+        let body = self.loop_body(&label)?;
+        Ok(Stmt::While(label, condition, body, None))
+    }
+
+    /// `for (init; condition; increment) body` becomes a block running
+    /// `init` and then a `while` loop carrying the increment.
+    fn for_stmt(&mut self, label: Option<Symbol>) -> Result<Stmt, Error> {
+        let keyword = self.previous().clone();
+        self.consume(TokenType::LeftParen, "Expect '(' after 'for'.")?;
+        let bare_for_in = self.check(TokenType::Identifier) && self.check_next(TokenType::In);
+        if bare_for_in || self.check(TokenType::Var) && self.iterates_after_var() {
+            self.match_one(TokenType::Var);
+            return self.for_in_stmt(label, &keyword);
+        }
+        let initializer = if self.match_one(TokenType::Semicolon) {
+            None
+        } else if self.match_one(TokenType::Var) {
+            Some(self.var_declaration()?)
+        } else {
+            Some(self.expression_stmt()?)
+        };
+        let condition = if self.check(TokenType::Semicolon) {
+            Expr::Literal(Literal::True)
+        } else {
+            self.expression()?
+        };
+        self.consume(TokenType::Semicolon, "Expect ';' after loop condition.")?;
+        let increment = if self.check(TokenType::RightParen) {
+            None
+        } else {
+            Some(self.expression()?)
+        };
+        self.consume(TokenType::RightParen, "Expect ')' after for clauses.")?;
+        let body = self.loop_body(&label)?;
+        let looped = Stmt::While(label, condition, body, increment);
+        Ok(match initializer {
+            Some(initializer) => Stmt::Block(vec![initializer, looped]),
+            None => looped,
+        })
+    }
+
+    /// Whether the `var` at the current token starts `var x in`.
+    fn iterates_after_var(&self) -> bool {
+        let at = |offset: usize| self.tokens.get(self.current + offset).map(|t| t.t_type);
+        at(1) == Some(TokenType::Identifier) && at(2) == Some(TokenType::In)
+    }
+
+    /// The rest of `for (var x in iterable) body`, from the variable name.
+    fn for_in_stmt(&mut self, label: Option<Symbol>, keyword: &Token) -> Result<Stmt, Error> {
+        let name = Self::symbol(&self.consume(TokenType::Identifier, "Expect variable name.")?);
+        self.consume(TokenType::In, "Expect 'in' after the variable name.")?;
+        let iterable = self.expression()?;
+        self.consume(
+            TokenType::RightParen,
+            "Expect ')' after the value to iterate.",
+        )?;
+        let body = self.loop_body(&label)?;
+        Ok(Stmt::ForIn(
+            SourceLocation {
+                line: keyword.line,
+                col: keyword.col,
+            },
+            label,
+            name,
+            iterable,
+            body,
+        ))
+    }
+
+    fn do_while_stmt(&mut self, label: Option<Symbol>) -> Result<Stmt, Error> {
+        let body = self.loop_body(&label)?;
+        self.consume(TokenType::While, "Expect 'while' after do body.")?;
+        self.consume(TokenType::LeftParen, "Expect '(' after 'while'.")?;
+        let condition = self.expression()?;
+        self.consume(TokenType::RightParen, "Expect ')' after condition.")?;
+        self.end_statement("Expect ';' after do-while loop.")?;
+        Ok(Stmt::DoWhile(label, body, condition))
+    }
+
+    /// `break` / `continue`, optionally followed by the label to target.
+    fn loop_control_stmt(&mut self) -> Result<Stmt, Error> {
+        let keyword = self.previous().clone();
+        let label = if self.check(TokenType::Identifier) && !self.at_implicit_semicolon() {
+            Some(Self::symbol(self.advance()))
+        } else {
+            None
+        };
+        let keyword_name = String::from_utf8_lossy(&keyword.lexeme).into_owned();
+        if self.loops.is_empty() {
+            return Err(Error::OutsideLoop {
+                keyword: keyword_name,
+                line: keyword.line,
+                col: keyword.col,
+            });
+        }
+        if let Some(label) = &label {
+            if !self.loops.iter().any(|l| l.as_ref() == Some(&label.name)) {
+                return Err(Error::UndefinedLabel {
+                    label: label.name.clone(),
+                    line: label.line,
+                    col: label.col,
+                });
+            }
+        }
+        self.end_statement(&format!("Expect ';' after '{}'.", keyword_name))?;
+        Ok(if keyword.t_type == TokenType::Break {
+            Stmt::Break(label)
+        } else {
+            Stmt::Continue(label)
+        })
+    }
+
+    fn return_stmt(&mut self) -> Result<Stmt, Error> {
+        let keyword = self.previous().clone();
+        let value = if self.check(TokenType::Semicolon) || self.at_implicit_semicolon() {
+            None
+        } else {
+            Some(self.expression()?)
+        };
+        self.end_statement("Expect ';' after return value.")?;
+        Ok(Stmt::Return(
+            SourceLocation {
+                line: keyword.line,
+                col: keyword.col,
+            },
+            value,
+        ))
+    }
+
+    fn block(&mut self) -> Result<Vec<Stmt>, Error> {
+        let mut statements = vec![];
+        while !self.check(TokenType::RightBrace) && !self.is_at_end() {
+            if let Some(stmt) = self.recovering_declaration() {
+                statements.push(stmt);
+            }
+        }
+        self.consume(TokenType::RightBrace, "Expect '}' after block.")?;
+        Ok(statements)
+    }
+
     fn print_stmt(&mut self) -> Result<Stmt, Error> {
         let expr = self.expression()?;
-        self.consume(TokenType::Semicolon, "Expected ; after value.")?;
+        self.end_statement("Expected ; after value.")?;
         let stmt = Stmt::Print(expr);
         Ok(stmt)
     }
 
+    fn eprint_stmt(&mut self) -> Result<Stmt, Error> {
+        let expr = self.expression()?;
+        self.end_statement("Expected ; after value.")?;
+        Ok(Stmt::Eprint(expr))
+    }
+
+    fn debug_stmt(&mut self) -> Result<Stmt, Error> {
+        let expr = self.expression()?;
+        self.end_statement("Expected ; after value.")?;
+        Ok(Stmt::Debug(expr))
+    }
+
+    fn assert_stmt(&mut self) -> Result<Stmt, Error> {
+        let loc = Self::location(self.previous());
+        let condition = self.expression()?;
+        let message = if self.match_one(TokenType::Comma) {
+            Some(self.expression()?)
+        } else {
+            None
+        };
+        self.end_statement("Expected ; after assertion.")?;
+        Ok(Stmt::Assert(loc, condition, message))
+    }
+
     fn expression_stmt(&mut self) -> Result<Stmt, Error> {
         let expr = self.expression()?;
-        self.consume(TokenType::Semicolon, "Expected ; after value.")?;
+        self.end_statement("Expected ; after value.")?;
         let stmt = Stmt::Expr(expr);
         Ok(stmt)
     }
 
     fn expression(&mut self) -> Result<Expr, Error> {
         // return self.equality();
-        return self.assignment();
+        self.nested(Self::assignment)
     }
 
+    /// Runs `parse` one nesting level deeper, failing once `max_depth` is hit.
+    fn nested<T>(&mut self, parse: fn(&mut Self) -> Result<T, Error>) -> Result<T, Error> {
+        if self.depth >= self.max_depth {
+            let current = self.peek();
+            return Err(Error::TooDeeplyNested {
+                line: current.line,
+                col: current.col,
+            });
+        }
+        self.depth += 1;
+        let result = parse(self);
+        self.depth -= 1;
+        result
+    }
+
+    /// Right-associative: `a = b = c` assigns `c` to `b`, then the result
+    /// of that to `a`.
     fn assignment(&mut self) -> Result<Expr, Error> {
-        let expr = self.equality()?;
+        if self.destructuring_ahead() {
+            let loc = Self::location(self.advance());
+            let names = self.name_list()?;
+            self.consume(TokenType::Equal, "Expect '=' after the names")?;
+            let value = self.assignment()?;
+            return Ok(Expr::AssignDestructure(loc, names, Box::new(value)));
+        }
+        let expr = self.or()?;
         if self.match_one(TokenType::Equal) {
             let equals = self.previous().clone();
             let assigned = self.assignment()?;
-            if let Expr::Variable(symbol) = &expr {
-                return Ok(Expr::Assign(symbol.clone(), Box::new(assigned)));
+            match expr {
+                Expr::Variable(symbol) => return Ok(Expr::Assign(symbol, Box::new(assigned))),
+                Expr::Index(object, loc, index) => {
+                    return Ok(Expr::SetIndex(object, loc, index, Box::new(assigned)))
+                }
+                _ => {}
             }
             return Err(Error::InvalidAssignment {
+                target: Self::describe_target(&expr),
                 line: equals.line,
-                col: -1,
+                col: equals.col,
             });
         }
         Ok(expr)
     }
 
+    /// Names what kind of expression ended up on the left of an `=`.
+    fn describe_target(expr: &Expr) -> &'static str {
+        match expr {
+            Expr::Literal(_) => "a literal",
+            Expr::Call(..) => "a function call",
+            Expr::Get(..) => "a property",
+            Expr::List(_) => "a list literal",
+            Expr::Comprehension(..) => "a list comprehension",
+            Expr::Grouping(..) => "an expression in parentheses",
+            Expr::Unary(..) | Expr::Binary(..) | Expr::Logical(..) => "the result of an operator",
+            Expr::This(_) => "'this'",
+            Expr::Super(..) => "'super'",
+            Expr::Assign(..) | Expr::AssignDestructure(..) | Expr::SetIndex(..) | Expr::Set(..) => {
+                "an assignment"
+            }
+            Expr::Variable(_) | Expr::Index(..) => "this expression",
+        }
+    }
+
+    fn or(&mut self) -> Result<Expr, Error> {
+        let mut expr = self.and()?;
+        while self.match_one(TokenType::Or) {
+            let right = Box::new(self.and()?);
+            expr = Expr::Logical(Box::new(expr), LogicalOp::Or, right);
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, Error> {
+        let mut expr = self.equality()?;
+        while self.match_one(TokenType::And) {
+            let right = Box::new(self.equality()?);
+            expr = Expr::Logical(Box::new(expr), LogicalOp::And, right);
+        }
+        Ok(expr)
+    }
+
     fn equality(&mut self) -> Result<Expr, Error> {
+        let start = self.peek().start;
         let mut expr = self.comparison()?;
         while self.matches(vec![TokenType::BangEqual, TokenType::EqualEqual]) {
             let operator = self.previous().clone(); // mutable borrow
             let right = Box::new(self.comparison()?);
-            let binary_op = Self::token_to_binary_operator(&operator);
+            let binary_op = Self::token_to_binary_operator(&operator, self.span_from(start));
             expr = Expr::Binary(Box::new(expr), binary_op, right)
         }
         Ok(expr)
     }
 
     fn comparison(&mut self) -> Result<Expr, Error> {
-        // Expr::Literal(Literal::Number(52.0))
-        let mut expr = self.term()?;
+        let start = self.peek().start;
+        // Expr::Literal(Literal::Number(52.0, None))
+        let mut expr = self.shift()?;
+        let mut previous: Option<Token> = None;
         while self.matches(vec![
             TokenType::Greater,
             TokenType::GreaterEqual,
             TokenType::Less,
             TokenType::LessEqual,
         ]) {
+            let operator = self.previous().clone();
+            if let Some(first) = previous {
+                return Err(Error::ChainedComparison {
+                    first: String::from_utf8_lossy(&first.lexeme).into_owned(),
+                    second: String::from_utf8_lossy(&operator.lexeme).into_owned(),
+                    line: operator.line,
+                    col: operator.col,
+                });
+            }
+            let right = self.shift()?;
+            let binary_op = Self::token_to_binary_operator(&operator, self.span_from(start));
+            expr = Expr::Binary(Box::new(expr), binary_op, Box::new(right));
+            previous = Some(operator);
+        }
+        Ok(expr)
+    }
+
+    /// Shifts bind looser than `+` and `-`, as in C: `1 << 2 + 1` is `8`.
+    fn shift(&mut self) -> Result<Expr, Error> {
+        let start = self.peek().start;
+        let mut expr = self.term()?;
+        while self.matches(vec![TokenType::LessLess, TokenType::GreaterGreater]) {
             let operator = self.previous().clone();
             let right = self.term()?;
-            let binary_op = Self::token_to_binary_operator(&operator);
-            expr = Expr::Binary(Box::new(expr), binary_op, Box::new(right))
+            let binary_op = Self::token_to_binary_operator(&operator, self.span_from(start));
+            expr = Expr::Binary(Box::new(expr), binary_op, Box::new(right));
         }
         Ok(expr)
     }
 
     fn term(&mut self) -> Result<Expr, Error> {
+        let start = self.peek().start;
         let mut expr = self.factor()?;
         while self.matches(vec![TokenType::Plus, TokenType::Minus]) {
             let operator = self.previous().clone();
             let right = self.factor()?;
-            let binary_op = Self::token_to_binary_operator(&operator);
+            let binary_op = Self::token_to_binary_operator(&operator, self.span_from(start));
             expr = Expr::Binary(Box::new(expr), binary_op, Box::new(right));
         }
         Ok(expr)
     }
 
     fn factor(&mut self) -> Result<Expr, Error> {
+        let start = self.peek().start;
         let mut expr = self.unary()?;
-        while self.matches(vec![TokenType::Slash, TokenType::Star]) {
+        while self.matches(vec![TokenType::Slash, TokenType::Star, TokenType::Div]) {
             let operator = self.previous().clone();
             let right = self.unary()?;
-            let binary_op = Self::token_to_binary_operator(&operator);
+            let binary_op = Self::token_to_binary_operator(&operator, self.span_from(start));
             expr = Expr::Binary(Box::new(expr), binary_op, Box::new(right))
         }
         Ok(expr)
@@ -163,12 +837,65 @@ impl Parser {
         if self.matches(vec![TokenType::Minus, TokenType::Bang]) {
             let operator = self.previous().clone();
             // one and only another unary.
-            let right = self.unary()?;
-            let unary_op = Self::token_to_unary_op(&operator);
+            let right = self.nested(Self::unary)?;
+            let unary_op = Self::token_to_unary_op(&operator, self.span_from(operator.start));
             return Ok(Expr::Unary(unary_op, Box::new(right)));
         }
 
-        self.primary()
+        self.call()
+    }
+
+    /// Calls, `.` and `?.` properties and `[]` indexing share one tier and
+    /// apply left to right in any mix, each wrapping what came before, so
+    /// `a.b().c` is a `Get` of `c` on a `Call` of a `Get` of `b` on `a`.
+    fn call(&mut self) -> Result<Expr, Error> {
+        let mut expr = self.primary()?;
+        loop {
+            if self.match_one(TokenType::LeftParen) {
+                expr = self.finish_call(expr)?;
+            } else if self.match_one(TokenType::Dot) || self.match_one(TokenType::QuestionDot) {
+                let nil_safe = self.previous().t_type == TokenType::QuestionDot;
+                let name =
+                    self.consume(TokenType::Identifier, "Expect property name after '.'.")?;
+                expr = Expr::Get(Box::new(expr), Self::symbol(&name), nil_safe);
+            } else if self.match_one(TokenType::LeftBracket) {
+                let bracket = self.previous().clone();
+                let index = self.expression()?;
+                self.consume(TokenType::RightBracket, "Expect ']' after index.")?;
+                expr = Expr::Index(
+                    Box::new(expr),
+                    SourceLocation {
+                        line: bracket.line,
+                        col: bracket.col,
+                    },
+                    Box::new(index),
+                );
+            } else {
+                break;
+            }
+        }
+        Ok(expr)
+    }
+
+    fn finish_call(&mut self, callee: Expr) -> Result<Expr, Error> {
+        let mut arguments = vec![];
+        if !self.check(TokenType::RightParen) {
+            loop {
+                arguments.push(self.expression()?);
+                if !self.match_one(TokenType::Comma) {
+                    break;
+                }
+            }
+        }
+        let paren = self.consume(TokenType::RightParen, "Expect ')' after arguments.")?;
+        Ok(Expr::Call(
+            Box::new(callee),
+            SourceLocation {
+                line: paren.line,
+                col: paren.col,
+            },
+            arguments,
+        ))
     }
 
     fn primary(&mut self) -> Result<Expr, Error> {
@@ -182,10 +909,11 @@ impl Parser {
             return Ok(Expr::Literal(Literal::Nil));
         }
         if self.matches(vec![TokenType::Number, TokenType::String]) {
-            let literal = self.previous().clone().literal;
-            match literal {
+            let token = self.previous().clone();
+            match token.literal {
                 Some(scanner::Literal::Number(n)) => {
-                    return Ok(Expr::Literal(Literal::Number(n)));
+                    let lexeme = String::from_utf8(token.lexeme).unwrap();
+                    return Ok(Expr::Literal(Literal::Number(n, Some(lexeme))));
                 }
                 Some(scanner::Literal::String(s)) => {
                     return Ok(Expr::Literal(Literal::String(s)));
@@ -201,7 +929,7 @@ impl Parser {
                     return Ok(Expr::Variable(Symbol {
                         name: s.clone(),
                         line: token.line,
-                        col: -1,
+                        col: token.col,
                     }));
                 }
                 Some(l) => {
@@ -216,100 +944,197 @@ impl Parser {
             }
         }
 
+        if self.match_one(TokenType::LeftBracket) {
+            let mut elements = vec![];
+            if !self.check(TokenType::RightBracket) {
+                let first = self.expression()?;
+                if self.match_one(TokenType::For) {
+                    return self.comprehension(first);
+                }
+                elements.push(first);
+                while self.match_one(TokenType::Comma) {
+                    elements.push(self.expression()?);
+                }
+            }
+            self.consume(TokenType::RightBracket, "Expect ']' after list elements.")?;
+            return Ok(Expr::List(elements));
+        }
         if self.match_one(TokenType::LeftParen) {
+            let paren = self.previous().clone();
             let expr = self.expression()?;
             self.consume(TokenType::RightParen, "Expect ')' after expression")?;
-            return Ok(Expr::Grouping(Box::new(expr)));
+            return Ok(Expr::Grouping(
+                SourceLocation {
+                    line: paren.line,
+                    col: paren.col,
+                },
+                Box::new(expr),
+            ));
         }
         let current = self.peek();
         Err(Error::ExpectedExpression {
             token_type: current.t_type,
             line: current.line,
-            col: -1,
+            col: current.col,
         })
     }
 
-    fn token_to_unary_op(token: &Token) -> UnaryOp {
+    /// The rest of `[element for (var name in iterable)]`, after `for`.
+    fn comprehension(&mut self, element: Expr) -> Result<Expr, Error> {
+        let keyword = self.previous().clone();
+        self.consume(TokenType::LeftParen, "Expect '(' after 'for'.")?;
+        self.consume(TokenType::Var, "Expect 'var' in a list comprehension.")?;
+        let name = Self::symbol(&self.consume(TokenType::Identifier, "Expect variable name.")?);
+        self.consume(TokenType::In, "Expect 'in' after the variable name.")?;
+        let iterable = self.expression()?;
+        self.consume(
+            TokenType::RightParen,
+            "Expect ')' after the list to iterate.",
+        )?;
+        self.consume(
+            TokenType::RightBracket,
+            "Expect ']' after a list comprehension.",
+        )?;
+        Ok(Expr::Comprehension(
+            Box::new(element),
+            SourceLocation {
+                line: keyword.line,
+                col: keyword.col,
+            },
+            name,
+            Box::new(iterable),
+        ))
+    }
+
+    fn symbol(token: &Token) -> Symbol {
+        Symbol {
+            name: String::from_utf8(token.lexeme.clone()).unwrap(), // Token Identifier stored in lexeme
+            line: token.line,
+            col: token.col,
+        }
+    }
+
+    /// From byte offset `start` to the end of the last token consumed.
+    fn span_from(&self, start: usize) -> Span {
+        Span {
+            start,
+            end: self.previous().end,
+        }
+    }
+
+    fn token_to_unary_op(token: &Token, span: Span) -> UnaryOp {
         let Token { t_type, .. } = token;
         let line = token.line;
-        let col = -1;
+        let col = token.col;
         match t_type {
             TokenType::Minus => UnaryOp {
                 op_type: UnaryOpType::Minus,
                 line,
                 col,
+                span,
             },
             TokenType::Bang => UnaryOp {
                 op_type: UnaryOpType::Bang,
                 line,
                 col,
+                span,
             },
             _ => UnaryOp {
                 op_type: UnaryOpType::Bang,
                 line,
-                col: -1,
+                col,
+                span,
             },
         }
     }
 
-    fn token_to_binary_operator(token: &Token) -> BinaryOp {
+    fn token_to_binary_operator(token: &Token, span: Span) -> BinaryOp {
         let line = token.line;
-        let col = -1;
+        let col = token.col;
         match token.t_type {
             TokenType::BangEqual => BinaryOp {
                 op_type: BinaryOpType::BangEqual,
                 line,
-                col: -1,
+                col,
+                span,
             },
             TokenType::EqualEqual => BinaryOp {
                 op_type: BinaryOpType::EqualEqual,
                 line,
-                col: -1,
+                col,
+                span,
             },
             TokenType::Greater => BinaryOp {
                 op_type: BinaryOpType::Greater,
                 line,
                 col,
+                span,
             },
             TokenType::GreaterEqual => BinaryOp {
                 op_type: BinaryOpType::GreaterEqual,
                 line,
                 col,
+                span,
             },
             TokenType::Less => BinaryOp {
                 op_type: BinaryOpType::Less,
                 line,
                 col,
+                span,
             },
             TokenType::Plus => BinaryOp {
                 op_type: BinaryOpType::Plus,
                 line,
                 col,
+                span,
             },
             TokenType::Minus => BinaryOp {
                 op_type: BinaryOpType::Minus,
                 line,
                 col,
+                span,
             },
             TokenType::LessEqual => BinaryOp {
                 op_type: BinaryOpType::LessEqual,
                 line,
                 col,
+                span,
             },
             TokenType::Slash => BinaryOp {
                 op_type: BinaryOpType::Slash,
                 line,
                 col,
+                span,
             },
             TokenType::Star => BinaryOp {
                 op_type: BinaryOpType::Star,
                 line,
                 col,
+                span,
+            },
+            TokenType::Div => BinaryOp {
+                op_type: BinaryOpType::Div,
+                line,
+                col,
+                span,
+            },
+            TokenType::LessLess => BinaryOp {
+                op_type: BinaryOpType::ShiftLeft,
+                line,
+                col,
+                span,
+            },
+            TokenType::GreaterGreater => BinaryOp {
+                op_type: BinaryOpType::ShiftRight,
+                line,
+                col,
+                span,
             },
             _ => BinaryOp {
                 op_type: BinaryOpType::LessEqual,
                 line,
-                col: -1,
+                col,
+                span,
             },
         }
     }
@@ -331,22 +1156,37 @@ impl Parser {
         false
     }
 
+    /// Skips tokens until the start of what looks like the next statement.
     fn synchronize(&mut self) {
         self.advance();
         while !self.is_at_end() {
-            if self.previous().t_type == TokenType::Semicolon {
+            if self.previous().t_type == TokenType::Semicolon
+                || self.asi && self.newline_before_current()
+            {
                 return;
             }
             match self.peek().t_type {
-                TokenType::If
+                TokenType::Assert
+                | TokenType::Break
+                | TokenType::Class
+                | TokenType::Const
+                | TokenType::Continue
+                | TokenType::Debug
+                | TokenType::Do
+                | TokenType::Eprint
+                | TokenType::If
                 | TokenType::Var
                 | TokenType::For
                 | TokenType::Fun
-                | TokenType::While => return,
+                | TokenType::While
+                | TokenType::Print
+                | TokenType::Repeat
+                | TokenType::Return
+                | TokenType::With => return,
                 _ => {}
             }
+            self.advance();
         }
-        self.advance();
     }
 
     fn check(&self, token_type: TokenType) -> bool {
@@ -357,6 +1197,39 @@ impl Parser {
         }
     }
 
+    /// Like `check`, for the token after the current one.
+    fn check_next(&self, token_type: TokenType) -> bool {
+        match self.tokens.get(self.current + 1) {
+            Some(token) => token.t_type == token_type,
+            None => false,
+        }
+    }
+
+    /// Whether the current statement can end here without a `;`: under
+    /// `asi`, at a line break, before a `}` or at the end of the input.
+    fn at_implicit_semicolon(&self) -> bool {
+        self.asi
+            && (self.newline_before_current()
+                || self.check(TokenType::RightBrace)
+                || self.is_at_end())
+    }
+
+    fn newline_before_current(&self) -> bool {
+        self.newline_before
+            .get(self.current)
+            .copied()
+            .unwrap_or(false)
+    }
+
+    /// Consumes the `;` that ends a statement, or accepts its absence
+    /// where `at_implicit_semicolon` allows.
+    fn end_statement(&mut self, message: &str) -> Result<(), Error> {
+        if self.match_one(TokenType::Semicolon) || self.at_implicit_semicolon() {
+            return Ok(());
+        }
+        self.consume(TokenType::Semicolon, message).map(|_| ())
+    }
+
     fn consume(&mut self, token_type: TokenType, message: &str) -> Result<Token, Error> {
         if self.check(token_type) {
             return Ok(self.advance().clone());
@@ -387,3 +1260,140 @@ impl Parser {
         self.peek().t_type == TokenType::Eof
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::interpreter;
+
+    fn run_asi(source: &str) -> Result<String, String> {
+        let program =
+            crate::parse_asi(source.to_string()).map_err(|errors| errors[0].message.clone())?;
+        let (mut interpreter, output) = interpreter();
+        interpreter.interpret(&program).map_err(|err| err.message)?;
+        Ok(output.contents())
+    }
+
+    #[test]
+    fn asi_accepts_line_breaks_as_semicolons() {
+        let output = run_asi("var x = 1\n{ print x }\nprint x + 1\n").unwrap();
+        assert_eq!(output, "1\n2\n");
+    }
+
+    #[test]
+    fn asi_still_accepts_semicolons() {
+        let output = run_asi("var x = 1; print x;\nprint x + 1;\n").unwrap();
+        assert_eq!(output, "1\n2\n");
+    }
+
+    #[test]
+    fn asi_ends_a_one_line_file_without_a_newline() {
+        assert_eq!(run_asi("print 1").unwrap(), "1\n");
+    }
+
+    #[test]
+    fn without_asi_a_semicolon_is_required() {
+        let errors = crate::parse("print 1\nprint 2\n".to_string()).unwrap_err();
+        assert_eq!(errors[0].message, "Expected ; after value, found 'print'");
+        let errors = crate::parse("print 1".to_string()).unwrap_err();
+        assert_eq!(
+            errors[0].message,
+            "Expected ; after value, found end of input"
+        );
+    }
+
+    #[test]
+    fn deep_nesting_is_an_error_not_a_crash() {
+        let grouping = |depth| format!("print {}1{};", "(".repeat(depth), ")".repeat(depth));
+        // The statement and its expression take two levels themselves.
+        assert!(crate::parse(grouping(DEFAULT_MAX_DEPTH - 2)).is_ok());
+        let errors = crate::parse(grouping(5000)).unwrap_err();
+        assert_eq!(errors[0].message, "Expression too deeply nested");
+    }
+
+    #[test]
+    fn errors_after_a_garbage_expression_are_still_reported() {
+        let source = "print 1; print 2 + * ;\nprint 3;\nprint (;\n";
+        let errors = crate::parse(source.to_string()).unwrap_err();
+        let found: Vec<_> = errors
+            .iter()
+            .map(|err| (err.message.as_str(), err.line))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                ("Expected expression, found '*'", 1),
+                ("Expected expression, found ';'", 3)
+            ]
+        );
+    }
+
+    fn parse_errors(source: &str) -> Vec<Error> {
+        let tokens = scanner::scan_tokens(source.to_string()).unwrap();
+        Parser::new(tokens).parse().unwrap_err()
+    }
+
+    #[test]
+    fn a_token_mismatch_displays_its_message_and_location() {
+        let errors = parse_errors("print 1\n  2;");
+        assert!(matches!(errors[0], Error::TokenMissmatch { .. }));
+        assert_eq!(
+            errors[0].to_string(),
+            "Expected ; after value, found '2' at line 2, column 3."
+        );
+    }
+
+    #[test]
+    fn chained_comparisons_get_a_helpful_message() {
+        let errors = crate::parse("print 1 < 2 < 3;".to_string()).unwrap_err();
+        assert_eq!(
+            errors[0].message,
+            "Comparisons can't be chained, write 'a < b and b < c' instead"
+        );
+    }
+
+    #[test]
+    fn messages_spell_tokens_as_in_the_source() {
+        let brace = scanner::scan_tokens("}".to_string()).unwrap().remove(0);
+        assert_eq!(
+            Error::UnexpectedToken(brace).message(),
+            "Unexpected token '}'"
+        );
+        assert_eq!(
+            parse_errors("print 1 }")[0].message(),
+            "Expected ; after value, found '}'"
+        );
+        assert_eq!(
+            parse_errors("print );")[0].message(),
+            "Expected expression, found ')'"
+        );
+    }
+
+    #[test]
+    fn an_invalid_assignment_names_its_target() {
+        let errors = parse_errors("var a = 1;\n  a.b() = 3;");
+        assert_eq!(
+            errors[0].message(),
+            "Invalid assignment target, cannot assign to a function call"
+        );
+        assert_eq!(errors[0].location(), (2, 9));
+    }
+
+    #[test]
+    fn calls_and_gets_chain_left_to_right() {
+        let tokens = scanner::scan_tokens("a.b().c".to_string()).unwrap();
+        let expr = Parser::new(tokens).parse_expression().unwrap();
+        let Expr::Get(call, c, false) = &expr else {
+            panic!("expected a get, got {:?}", expr);
+        };
+        let Expr::Call(get, _, args) = call.as_ref() else {
+            panic!("expected a call, got {:?}", call);
+        };
+        let Expr::Get(a, b, false) = get.as_ref() else {
+            panic!("expected a get, got {:?}", get);
+        };
+        assert!(matches!(a.as_ref(), Expr::Variable(a) if a.name == "a"));
+        assert_eq!((b.name.as_str(), c.name.as_str()), ("b", "c"));
+        assert!(args.is_empty());
+    }
+}
@@ -0,0 +1,270 @@
+use crate::error_format::{Diagnostic, ErrorKind};
+use crate::expr::{Stmt, Symbol};
+use crate::optimizer;
+use crate::resolver::Lints;
+use crate::scanner::{self, Token, TokenType};
+use crate::tree_interpreter::{Interpreter, RuntimeError};
+use std::io::Write;
+
+/// Runs the lines typed at the prompt against one interpreter, so
+/// declarations carry over from one line to the next.
+#[derive(Default)]
+pub struct Repl {
+    pub interpreter: Interpreter,
+    /// Parse lines with automatic semicolon insertion, as `--asi` does.
+    pub asi: bool,
+    /// Fold constant expressions before running a line, as `--optimize`
+    /// does.
+    pub optimize: bool,
+    /// Lints for the static checks each line goes through.
+    pub lints: Lints,
+    /// Lines ended with `\`, waiting for the line that completes them.
+    pending: String,
+    /// The tokens of `pending`, each line scanned once as it came in and
+    /// renumbered to its place among the held lines.
+    pending_tokens: Vec<Token>,
+    /// Set once a held line didn't scan on its own, as a string or comment
+    /// that runs on to the next line doesn't. The held lines are then
+    /// scanned together when the last one comes.
+    rescan: bool,
+}
+
+impl Repl {
+    /// Runs one line of input. Errors are handed back rather than printed,
+    /// and the session stays usable after them. The line goes through the
+    /// same static checks as a script, knowing the globals declared so far.
+    ///
+    /// A line ending in `\` is held back, without the backslash, until a
+    /// line completes it. Each line is scanned as it comes in and only its
+    /// tokens are added to the held ones, so a long continued definition
+    /// isn't scanned again for every line; the tokens come out as if all
+    /// the lines had been scanned together.
+    ///
+    /// The value of the last expression statement is kept in the global
+    /// `_`, so the next line can build on it. A runtime error's bindings,
+    /// with `dump_env_on_error` on, go to the interpreter's error output.
+    pub fn run_line(&mut self, line: &str) -> Result<(), Vec<Diagnostic>> {
+        if let Some(start) = line.trim_end().strip_suffix('\\') {
+            let start = format!("{}\n", start);
+            if !self.rescan {
+                match self.scan_pending(&start) {
+                    // Only the line that ends the input keeps its end of file.
+                    Ok(tokens) => self.pending_tokens.extend(
+                        tokens
+                            .into_iter()
+                            .filter(|token| token.t_type != TokenType::Eof),
+                    ),
+                    Err(_) => self.rescan = true,
+                }
+            }
+            self.pending.push_str(&start);
+            return Ok(());
+        }
+        let program = match self.tokens(line) {
+            Some(tokens) => crate::parse_line_tokens(tokens, self.asi),
+            None => crate::parse_line(&(self.pending.clone() + line), self.asi),
+        };
+        self.pending.clear();
+        self.pending_tokens.clear();
+        self.rescan = false;
+        let program = program?;
+        let known = self.interpreter.global_names();
+        let program = crate::check_program_in(program, self.lints, &known)?;
+        let program = if self.optimize {
+            optimizer::fold_constants(&program)
+        } else {
+            program
+        };
+        self.run_program(&program).map_err(|err| {
+            if let Some(bindings) = &err.bindings {
+                let dump = format!("Environment at the error:\n{}", bindings);
+                // A failed write only loses the dump; the error is still
+                // reported.
+                let _ = self.interpreter.error_output.write_all(dump.as_bytes());
+            }
+            vec![Diagnostic {
+                kind: ErrorKind::Runtime,
+                message: err.message,
+                line: err.line,
+                col: err.col,
+            }]
+        })
+    }
+
+    /// The held tokens with those of `line` after them, or `None` if the
+    /// lines have to be scanned together instead. Scan errors are left to
+    /// that scan, so they are reported where they are in the whole input.
+    fn tokens(&self, line: &str) -> Option<Vec<Token>> {
+        if self.rescan {
+            return None;
+        }
+        let mut tokens = self.pending_tokens.clone();
+        tokens.extend(self.scan_pending(line).ok()?);
+        Some(tokens)
+    }
+
+    /// Scans `source` as if it came right after the held lines.
+    fn scan_pending(&self, source: &str) -> Result<Vec<Token>, Vec<scanner::Error>> {
+        let mut tokens = if self.asi {
+            scanner::scan_tokens_with_newlines(source.to_string())
+        } else {
+            scanner::scan_tokens(source.to_string())
+        }?;
+        let lines = self.pending.matches('\n').count();
+        for token in &mut tokens {
+            token.line += lines;
+            token.start += self.pending.len();
+            token.end += self.pending.len();
+        }
+        Ok(tokens)
+    }
+
+    fn run_program(&mut self, program: &[Stmt]) -> Result<(), RuntimeError> {
+        self.interpreter.hoist_functions(program);
+        for stmt in program {
+            match stmt {
+                Stmt::Expr(expr) => {
+                    let val = self.interpreter.evaluate_expr_stmt(stmt, expr)?;
+                    let last = Symbol {
+                        name: "_".to_string(),
+                        line: 0,
+                        col: -1,
+                    };
+                    self.interpreter
+                        .globals
+                        .borrow_mut()
+                        .define(last, Some(val));
+                }
+                _ => self.interpreter.evaluate(stmt)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether the last line asked to be continued, so the prompt can say so.
+    pub fn is_continuing(&self) -> bool {
+        !self.pending.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tree_interpreter::SharedBuffer;
+
+    fn repl() -> (Repl, SharedBuffer) {
+        let mut repl = Repl::default();
+        let output = SharedBuffer::default();
+        repl.interpreter.output = Box::new(output.clone());
+        (repl, output)
+    }
+
+    #[test]
+    fn the_last_statement_needs_no_semicolon() {
+        let (mut repl, output) = repl();
+        repl.run_line("print 1").unwrap();
+        assert_eq!(output.contents(), "1\n");
+    }
+
+    #[test]
+    fn a_backslash_continues_the_line() {
+        let (mut repl, output) = repl();
+        repl.run_line("print 1 + \\").unwrap();
+        assert!(repl.is_continuing());
+        assert_eq!(output.contents(), "");
+        repl.run_line("2;").unwrap();
+        assert!(!repl.is_continuing());
+        assert_eq!(output.contents(), "3\n");
+    }
+
+    #[test]
+    fn underscore_holds_the_last_result() {
+        let (mut repl, output) = repl();
+        repl.run_line("1 + 1;").unwrap();
+        repl.run_line("print _ * 3;").unwrap();
+        assert_eq!(output.contents(), "6\n");
+    }
+
+    #[test]
+    fn lines_run_with_the_interpreter_settings() {
+        let (mut repl, output) = repl();
+        let trace = SharedBuffer::default();
+        repl.interpreter.error_output = Box::new(trace.clone());
+        repl.interpreter.trace = true;
+        repl.interpreter.float_precision = Some(3);
+        repl.optimize = true;
+        repl.run_line("1 / 3").unwrap();
+        repl.run_line("print 2 * 3;").unwrap();
+        assert_eq!(output.contents(), "6\n");
+        assert_eq!(
+            trace.contents(),
+            "[trace] 0.3333333333333333;\n[trace] => 0.333\n[trace] print 6;\n"
+        );
+    }
+
+    #[test]
+    fn asi_ends_statements_at_continued_line_breaks() {
+        let (mut repl, output) = repl();
+        repl.run_line("print 1 \\").unwrap();
+        assert!(repl.run_line("print 2").is_err());
+        repl.asi = true;
+        repl.run_line("print 1 \\").unwrap();
+        repl.run_line("print 2").unwrap();
+        assert_eq!(output.contents(), "1\n2\n");
+    }
+
+    #[test]
+    fn runtime_errors_can_dump_the_environment() {
+        let (mut repl, _) = repl();
+        let errors = SharedBuffer::default();
+        repl.interpreter.error_output = Box::new(errors.clone());
+        repl.interpreter.dump_env_on_error = true;
+        repl.run_line("var x = 1;").unwrap();
+        let err = repl.run_line("x + nil").unwrap_err();
+        assert_eq!(err[0].kind, ErrorKind::Runtime);
+        assert!(errors
+            .contents()
+            .starts_with("Environment at the error:\nglobals:\n  x = 1\n"));
+    }
+
+    #[test]
+    fn continued_lines_scan_like_the_whole_definition() {
+        let (mut repl, output) = repl();
+        let lines = ["fun add(a, b) {", "  return a + b;", "}"];
+        repl.run_line(&format!("{} \\", lines[0])).unwrap();
+        repl.run_line(&format!("{} \\", lines[1])).unwrap();
+        let fields = |tokens: Vec<Token>| -> Vec<_> {
+            tokens
+                .into_iter()
+                .map(|token| (token.t_type, token.line, token.col, token.start, token.end))
+                .collect()
+        };
+        let whole = scanner::scan_tokens(lines.join(" \n")).unwrap();
+        assert_eq!(fields(repl.tokens(lines[2]).unwrap()), fields(whole));
+        repl.run_line(lines[2]).unwrap();
+        repl.run_line("print add(1, 2);").unwrap();
+        assert_eq!(output.contents(), "3\n");
+    }
+
+    #[test]
+    fn errors_in_a_continued_definition_point_at_their_line() {
+        let (mut repl, _) = repl();
+        repl.run_line("fun f() { \\").unwrap();
+        repl.run_line("  var = 1; \\").unwrap();
+        let errors = repl.run_line("}").unwrap_err();
+        assert_eq!((errors[0].line, errors[0].col), (2, 7));
+        repl.run_line("fun f() { \\").unwrap();
+        repl.run_line("  return nil + 1; \\").unwrap();
+        repl.run_line("}").unwrap();
+        let errors = repl.run_line("f();").unwrap_err();
+        assert_eq!(errors[0].line, 2);
+    }
+
+    #[test]
+    fn a_string_can_run_on_to_a_continued_line() {
+        let (mut repl, output) = repl();
+        repl.run_line("print \"a \\").unwrap();
+        repl.run_line("b\";").unwrap();
+        assert_eq!(output.contents(), "a \nb\n");
+    }
+}
@@ -0,0 +1,624 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use crate::error_format;
+use crate::expr::{Expr, FunDecl, SourceLocation, Stmt, Symbol};
+use crate::natives;
+use crate::tree_interpreter::{closest_name, Environment};
+
+/// A problem found without running the program.
+#[derive(Clone, Debug)]
+pub struct Error {
+    pub message: String,
+    pub line: usize,
+    pub col: i64,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} at line {}, column {}.",
+            self.message, self.line, self.col
+        )
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Warnings that are off unless asked for, as what they flag is legal and
+/// often intended.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Lints {
+    /// An expression statement, such as `1 + 1;`, whose value is thrown
+    /// away without anything having happened.
+    pub unused_results: bool,
+    /// A local declared with the name of a variable from an enclosing
+    /// scope, which it hides for the rest of its own scope.
+    pub shadowing: bool,
+}
+
+/// A name declared in a local scope.
+struct Local {
+    symbol: Symbol,
+    /// Set once the name is read. Parameters and functions start out used
+    /// so they are never warned about.
+    used: bool,
+}
+
+/// Walks the program once before it runs and reports variables that can
+/// never be found. Globals may be used before their declaration, as long
+/// as the script declares them somewhere at the top level.
+///
+/// Local variables that are never read are reported as warnings when
+/// their scope ends, unless their name starts with `_`.
+pub struct Resolver {
+    globals: HashSet<String>,
+    /// Where the script's own globals are declared, as natives have no
+    /// declaration to point at.
+    global_decls: HashMap<String, Symbol>,
+    scopes: Vec<HashMap<String, Local>>,
+    /// Locals whose scope has already ended, so a later read of the name
+    /// can say where it was declared instead of just "undefined".
+    ended: HashMap<String, Symbol>,
+    errors: Vec<Error>,
+    lints: Lints,
+    /// Whether a function body may use globals that aren't declared yet,
+    /// as later input can still declare them before the call.
+    late_globals: bool,
+    /// How many function bodies the walk is inside of.
+    function_depth: usize,
+}
+
+impl Default for Resolver {
+    fn default() -> Self {
+        let mut builtins = Environment::new();
+        natives::define_globals(&mut builtins);
+        Resolver {
+            globals: builtins.names().iter().cloned().collect(),
+            global_decls: HashMap::new(),
+            scopes: Vec::new(),
+            ended: HashMap::new(),
+            errors: Vec::new(),
+            lints: Lints::default(),
+            late_globals: false,
+            function_depth: 0,
+        }
+    }
+}
+
+pub fn resolve(program: &[Stmt], lints: Lints) -> Result<(), Vec<Error>> {
+    run(
+        Resolver {
+            lints,
+            ..Resolver::default()
+        },
+        program,
+    )
+}
+
+/// Like `resolve`, for a program that runs where `known` globals already
+/// exist, such as a line typed at the REPL after earlier ones. More input
+/// can follow, so a function body may also use a global declared later.
+pub fn resolve_in(program: &[Stmt], lints: Lints, known: &[String]) -> Result<(), Vec<Error>> {
+    let mut resolver = Resolver {
+        lints,
+        late_globals: true,
+        ..Resolver::default()
+    };
+    resolver.globals.extend(known.iter().cloned());
+    run(resolver, program)
+}
+
+fn run(mut resolver: Resolver, program: &[Stmt]) -> Result<(), Vec<Error>> {
+    resolver.resolve(program);
+    if resolver.errors.is_empty() {
+        Ok(())
+    } else {
+        Err(resolver.errors)
+    }
+}
+
+impl Resolver {
+    pub fn resolve(&mut self, program: &[Stmt]) {
+        for stmt in program {
+            let names = match stmt {
+                Stmt::VarDecl(name, _) | Stmt::ConstDecl(name, _) => std::slice::from_ref(name),
+                Stmt::VarDestructure(_, names, _) => names.as_slice(),
+                Stmt::FunDecl(decl) => std::slice::from_ref(&decl.name),
+                _ => &[],
+            };
+            for name in names {
+                self.globals.insert(name.name.clone());
+                self.global_decls
+                    .entry(name.name.clone())
+                    .or_insert_with(|| name.clone());
+            }
+        }
+        for stmt in program {
+            self.resolve_stmt(stmt);
+        }
+    }
+
+    fn resolve_stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Expr(expr) => {
+                if self.lints.unused_results {
+                    Self::check_unused_result(expr);
+                }
+                self.resolve_expr(expr)
+            }
+            Stmt::Print(expr) | Stmt::Eprint(expr) | Stmt::Debug(expr) => self.resolve_expr(expr),
+            Stmt::Assert(_, condition, message) => {
+                self.resolve_expr(condition);
+                if let Some(message) = message {
+                    self.resolve_expr(message);
+                }
+            }
+            // A local function was already declared when its scope was
+            // hoisted.
+            Stmt::FunDecl(decl) => self.resolve_function(decl),
+            Stmt::If(cond, then_branch, else_branch) => {
+                Self::check_condition(cond);
+                self.resolve_expr(cond);
+                self.resolve_stmt(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.resolve_stmt(else_branch);
+                }
+            }
+            Stmt::VarDecl(name, initializer) => {
+                if let Some(initializer) = initializer {
+                    self.resolve_expr(initializer);
+                }
+                self.declare(name, false);
+            }
+            Stmt::ConstDecl(name, initializer) => {
+                self.resolve_expr(initializer);
+                self.declare(name, false);
+            }
+            Stmt::VarDestructure(_, names, initializer) => {
+                self.resolve_expr(initializer);
+                for name in names {
+                    self.declare(name, false);
+                }
+            }
+            Stmt::Block(stmts) => {
+                self.scopes.push(HashMap::new());
+                self.hoist(stmts);
+                for stmt in stmts {
+                    self.resolve_stmt(stmt);
+                }
+                self.end_scope();
+            }
+            Stmt::Repeat(_, _, count, body) => {
+                self.resolve_expr(count);
+                self.resolve_stmt(body);
+            }
+            Stmt::While(_, condition, body, increment) => {
+                Self::check_condition(condition);
+                self.resolve_expr(condition);
+                self.resolve_stmt(body);
+                if let Some(increment) = increment {
+                    self.resolve_expr(increment);
+                }
+            }
+            Stmt::ForIn(_, _, name, iterable, body) => {
+                self.resolve_expr(iterable);
+                self.scopes.push(HashMap::new());
+                self.declare(name, false);
+                self.resolve_stmt(body);
+                self.end_scope();
+            }
+            Stmt::DoWhile(_, body, condition) => {
+                Self::check_condition(condition);
+                self.resolve_stmt(body);
+                self.resolve_expr(condition);
+            }
+            Stmt::Empty | Stmt::Break(_) | Stmt::Continue(_) => {}
+            Stmt::Return(_, value) => {
+                if let Some(value) = value {
+                    self.resolve_expr(value);
+                }
+            }
+        }
+    }
+
+    /// `if (x = 1)` is almost always a mistyped `==`. Extra parentheses,
+    /// as in `if ((x = next()))`, say the assignment is meant.
+    fn check_condition(condition: &Expr) {
+        if let Expr::Assign(name, _) = condition {
+            error_format::format_warning(
+                &format!(
+                    "Assignment to '{}' used as a condition, did you mean '=='?",
+                    name.name
+                ),
+                name.line,
+                name.col,
+            );
+        }
+    }
+
+    /// Only expressions that have somewhere to point are reported, which
+    /// leaves out a lone literal.
+    fn check_unused_result(expr: &Expr) {
+        if has_effects(expr) {
+            return;
+        }
+        if let Some(loc) = position(expr) {
+            error_format::format_warning("Expression result is unused", loc.line, loc.col);
+        }
+    }
+
+    fn resolve_function(&mut self, decl: &FunDecl) {
+        self.function_depth += 1;
+        self.scopes.push(HashMap::new());
+        for param in decl.params.iter().chain(&decl.rest) {
+            self.declare(param, true);
+        }
+        self.hoist(&decl.body);
+        for stmt in &decl.body {
+            self.resolve_stmt(stmt);
+        }
+        self.end_scope();
+        self.function_depth -= 1;
+    }
+
+    /// Functions are visible throughout their scope, as the interpreter
+    /// binds them all before running it.
+    fn hoist(&mut self, stmts: &[Stmt]) {
+        for stmt in stmts {
+            if let Stmt::FunDecl(decl) = stmt {
+                self.declare(&decl.name, true);
+            }
+        }
+    }
+
+    fn end_scope(&mut self) {
+        let Some(scope) = self.scopes.pop() else {
+            return;
+        };
+        for (name, local) in &scope {
+            self.ended.insert(name.clone(), local.symbol.clone());
+        }
+        let mut unused: Vec<_> = scope
+            .into_values()
+            .filter(|local| !local.used && !local.symbol.name.starts_with('_'))
+            .map(|local| local.symbol)
+            .collect();
+        unused.sort_by_key(|symbol| (symbol.line, symbol.col));
+        for symbol in unused {
+            error_format::format_warning(
+                &format!("Variable '{}' is declared but never used", symbol.name),
+                symbol.line,
+                symbol.col,
+            );
+        }
+    }
+
+    fn resolve_expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Assign(name, value) => {
+                self.resolve_expr(value);
+                self.check_defined(name, false);
+            }
+            Expr::AssignDestructure(_, names, value) => {
+                self.resolve_expr(value);
+                for name in names {
+                    self.check_defined(name, false);
+                }
+            }
+            Expr::Variable(name) => self.check_defined(name, true),
+            Expr::Unary(_, operand) | Expr::Grouping(_, operand) | Expr::Get(operand, ..) => {
+                self.resolve_expr(operand)
+            }
+            Expr::Binary(lhs, _, rhs) | Expr::Logical(lhs, _, rhs) | Expr::Set(lhs, _, rhs) => {
+                self.resolve_expr(lhs);
+                self.resolve_expr(rhs);
+            }
+            Expr::Call(callee, _, args) => {
+                self.resolve_expr(callee);
+                for arg in args {
+                    self.resolve_expr(arg);
+                }
+            }
+            Expr::Index(object, _, index) => {
+                self.resolve_expr(object);
+                self.resolve_expr(index);
+            }
+            Expr::SetIndex(object, _, index, value) => {
+                self.resolve_expr(object);
+                self.resolve_expr(index);
+                self.resolve_expr(value);
+            }
+            Expr::List(elements) => {
+                for element in elements {
+                    self.resolve_expr(element);
+                }
+            }
+            Expr::Comprehension(element, _, name, iterable) => {
+                self.resolve_expr(iterable);
+                self.scopes.push(HashMap::new());
+                self.declare(name, false);
+                self.resolve_expr(element);
+                self.end_scope();
+            }
+            Expr::Literal(_) | Expr::Super(..) | Expr::This(_) => {}
+        }
+    }
+
+    /// Top-level declarations were collected up front, so only locals need
+    /// declaring as they are reached. A global can be declared again and
+    /// simply replaces the old one, but a local can't share its scope with
+    /// another of the same name.
+    fn declare(&mut self, name: &Symbol, used: bool) {
+        let Some(scope) = self.scopes.last() else {
+            return;
+        };
+        if let Some(existing) = scope.get(&name.name) {
+            self.errors.push(Error {
+                message: format!(
+                    "Variable '{}' is already declared in this scope (at line {}, column {})",
+                    name.name, existing.symbol.line, existing.symbol.col
+                ),
+                line: name.line,
+                col: name.col,
+            });
+            return;
+        }
+        if self.lints.shadowing {
+            self.check_shadowing(name);
+        }
+        let local = Local {
+            symbol: name.clone(),
+            used,
+        };
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(name.name.clone(), local);
+        }
+    }
+
+    /// Warns if `name`, about to be declared in the innermost scope, hides
+    /// a variable from further out.
+    fn check_shadowing(&self, name: &Symbol) {
+        let Some((_, outer)) = self.scopes.split_last() else {
+            return;
+        };
+        let shadowed = outer
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(&name.name).map(|local| &local.symbol))
+            .or_else(|| self.global_decls.get(&name.name));
+        if let Some(shadowed) = shadowed {
+            error_format::format_warning(
+                &format!(
+                    "Variable '{}' shadows the one declared at line {}, column {}",
+                    name.name, shadowed.line, shadowed.col
+                ),
+                name.line,
+                name.col,
+            );
+        }
+    }
+
+    /// Assigning to a variable doesn't count as using it, only reading does.
+    fn check_defined(&mut self, name: &Symbol, read: bool) {
+        let local = self
+            .scopes
+            .iter_mut()
+            .rev()
+            .find_map(|scope| scope.get_mut(&name.name));
+        let found = match local {
+            Some(local) => {
+                local.used |= read;
+                true
+            }
+            None => self.globals.contains(&name.name),
+        };
+        let deferred = self.late_globals && self.function_depth > 0;
+        if !found && !deferred {
+            let message = match self.ended.get(&name.name) {
+                Some(declared) => format!(
+                    "Variable '{}' escapes its scope (declared at line {}, column {})",
+                    name.name, declared.line, declared.col
+                ),
+                None => {
+                    let visible = self
+                        .scopes
+                        .iter()
+                        .flat_map(|scope| scope.keys())
+                        .chain(&self.globals)
+                        .cloned();
+                    match closest_name(&name.name, visible) {
+                        Some(close) => format!(
+                            "Undefined variable '{}'. Did you mean '{}'?",
+                            name.name, close
+                        ),
+                        None => format!("Undefined variable '{}'", name.name),
+                    }
+                }
+            };
+            self.errors.push(Error {
+                message,
+                line: name.line,
+                col: name.col,
+            });
+        }
+    }
+}
+
+/// Whether evaluating `expr` can change anything, other than by failing.
+fn has_effects(expr: &Expr) -> bool {
+    match expr {
+        Expr::Assign(..)
+        | Expr::AssignDestructure(..)
+        | Expr::Set(..)
+        | Expr::SetIndex(..)
+        | Expr::Call(..) => true,
+        Expr::Unary(_, operand) | Expr::Grouping(_, operand) | Expr::Get(operand, ..) => {
+            has_effects(operand)
+        }
+        Expr::Binary(lhs, _, rhs) | Expr::Logical(lhs, _, rhs) | Expr::Index(lhs, _, rhs) => {
+            has_effects(lhs) || has_effects(rhs)
+        }
+        Expr::Comprehension(element, _, _, iterable) => {
+            has_effects(element) || has_effects(iterable)
+        }
+        Expr::List(elements) => elements.iter().any(has_effects),
+        Expr::Literal(_) | Expr::Super(..) | Expr::This(_) | Expr::Variable(_) => false,
+    }
+}
+
+/// Where to point at `expr`: its operator, its name, or its opening
+/// bracket. Literals don't keep a position.
+fn position(expr: &Expr) -> Option<SourceLocation> {
+    let at = |line, col| Some(SourceLocation { line, col });
+    match expr {
+        Expr::Unary(op, _) => at(op.line, op.col),
+        Expr::Binary(_, op, _) => at(op.line, op.col),
+        Expr::Variable(name) | Expr::Get(_, name, _) => at(name.line, name.col),
+        Expr::Grouping(loc, _)
+        | Expr::Index(_, loc, _)
+        | Expr::Comprehension(_, loc, ..)
+        | Expr::This(loc)
+        | Expr::Super(loc, _) => Some(loc.clone()),
+        Expr::Logical(lhs, ..) => position(lhs),
+        Expr::List(elements) => elements.iter().find_map(position),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repl::Repl;
+    use crate::test_support::{run, warnings};
+    use crate::tree_interpreter::SharedBuffer;
+
+    fn resolve_source(source: &str) -> Result<(), Vec<Error>> {
+        let program = crate::parse(source.to_string()).unwrap();
+        resolve(&program, Lints::default())
+    }
+
+    #[test]
+    fn local_redeclaration_is_an_error() {
+        let errors = resolve_source("{ var a = 1; var a = 2; print a; }").unwrap_err();
+        assert_eq!(
+            errors[0].message,
+            "Variable 'a' is already declared in this scope (at line 1, column 7)"
+        );
+        assert_eq!((errors[0].line, errors[0].col), (1, 18));
+    }
+
+    #[test]
+    fn undefined_variables_suggest_the_closest_name() {
+        let errors = resolve_source("var print_count = 1; { var total = 2; print totl; }");
+        assert_eq!(
+            errors.unwrap_err()[0].message,
+            "Undefined variable 'totl'. Did you mean 'total'?"
+        );
+        let errors = resolve_source("var print_count = 1; print print_cont;");
+        assert_eq!(
+            errors.unwrap_err()[0].message,
+            "Undefined variable 'print_cont'. Did you mean 'print_count'?"
+        );
+    }
+
+    #[test]
+    fn global_redeclaration_is_allowed() {
+        assert!(resolve_source("var a = 1; var a = 2; print a;").is_ok());
+    }
+
+    #[test]
+    fn scripts_are_checked_before_they_run() {
+        let err = run("print 1; { var a = 1; var a = 2; print a; }").unwrap_err();
+        assert!(err.starts_with("Variable 'a' is already declared"));
+    }
+
+    #[test]
+    fn repl_lines_are_checked_with_earlier_globals() {
+        let mut repl = Repl::default();
+        repl.interpreter.output = Box::new(SharedBuffer::default());
+        assert!(repl.run_line("var x = 1;").is_ok());
+        assert!(repl.run_line("print x;").is_ok());
+        let errors = repl
+            .run_line("{ var a = 1; var a = 2; print a; }")
+            .unwrap_err();
+        assert!(errors[0]
+            .message
+            .starts_with("Variable 'a' is already declared"));
+        assert!(repl.run_line("print y;").is_err());
+    }
+
+    #[test]
+    fn repl_functions_may_use_globals_declared_later() {
+        let mut repl = Repl::default();
+        assert!(repl.run_line("fun f() { return g(); }").is_ok());
+        assert!(repl.run_line("fun g() { return 5; }").is_ok());
+        assert!(repl.run_line("f();").is_ok());
+    }
+
+    #[test]
+    fn unused_locals_are_warned_about() {
+        assert_eq!(
+            warnings("{ var unused = 1; var used = 2; print used; }", Lints::default()),
+            "[{ \"line\": 1, \"col\": 7, \"message\": \"Variable 'unused' is declared but never used\", \"kind\": \"warning\" }]"
+        );
+        assert_eq!(
+            warnings("{ var used = 2; print used; }", Lints::default()),
+            "[]"
+        );
+    }
+
+    #[test]
+    fn reading_a_block_local_after_its_block_is_an_error() {
+        let errors = resolve_source("{ var a = 1; } print a;").unwrap_err();
+        assert_eq!(
+            errors[0].message,
+            "Variable 'a' escapes its scope (declared at line 1, column 7)"
+        );
+        let closure = "
+            fun make() { var n = 0; fun inc() { n = n + 1; return n; } return inc; }
+            var f = make(); f(); print f();";
+        assert!(resolve_source(closure).is_ok());
+        assert_eq!(run(closure).unwrap(), "2\n");
+    }
+
+    #[test]
+    fn an_assignment_as_a_condition_is_warned_about() {
+        assert_eq!(
+            warnings("var x = 0; if (x = 1) {}", Lints::default()),
+            "[{ \"line\": 1, \"col\": 16, \"message\": \"Assignment to 'x' used as a condition, did you mean '=='?\", \"kind\": \"warning\" }]"
+        );
+        assert_eq!(
+            warnings("var x = 0; if (x == 1) {}", Lints::default()),
+            "[]"
+        );
+    }
+
+    #[test]
+    fn unused_results_are_warned_about_when_asked() {
+        let lints = Lints {
+            unused_results: true,
+            ..Lints::default()
+        };
+        assert_eq!(
+            warnings("1 + 1; print 2;", lints),
+            "[{ \"line\": 1, \"col\": 3, \"message\": \"Expression result is unused\", \"kind\": \"warning\" }]"
+        );
+        assert_eq!(warnings("1 + 1;", Lints::default()), "[]");
+    }
+
+    #[test]
+    fn shadowing_is_warned_about_only_when_asked() {
+        let source = "var x = 1; { var x = 2; print x; }";
+        let lints = Lints {
+            shadowing: true,
+            ..Lints::default()
+        };
+        assert_eq!(
+            warnings(source, lints),
+            "[{ \"line\": 1, \"col\": 18, \"message\": \"Variable 'x' shadows the one declared at line 1, column 5\", \"kind\": \"warning\" }]"
+        );
+        assert_eq!(warnings(source, Lints::default()), "[]");
+    }
+}
@@ -1,6 +1,7 @@
-use std::{collections::HashMap, io::Error};
+use std::collections::HashMap;
+use std::fmt;
 
-use crate::error_format::format_error;
+use crate::error_format;
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum TokenType {
@@ -9,6 +10,9 @@ pub enum TokenType {
     RightParen,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
+    Colon,
     Comma,
     Dot,
     Minus,
@@ -26,33 +30,121 @@ pub enum TokenType {
     GreaterEqual,
     Less,
     LessEqual,
+    LessLess,
+    GreaterGreater,
+    DotDotDot,
+    QuestionDot,
 
     // Literals.
     Identifier,
     String,
     Number,
+    /// A line break; only produced in `emit_newlines` mode.
+    Newline,
+    /// Only produced when the scanner keeps comments; the lexeme is the
+    /// whole comment including its delimiters.
+    Comment,
 
     // Keywords.
     And,
+    Assert,
+    Break,
     Class,
+    Const,
+    Continue,
+    Debug,
+    Div,
+    Do,
     Else,
+    Eprint,
     False,
     Fun,
     For,
     If,
+    In,
     Nil,
     Or,
     Print,
+    Repeat,
     Return,
     Super,
     This,
     True,
     Var,
     While,
+    With,
 
     Eof,
 }
 
+impl TokenType {
+    /// How the token reads in messages: quoted as written in the source,
+    /// or in words for the kinds whose spelling varies.
+    pub fn describe(self) -> &'static str {
+        match self {
+            TokenType::LeftParen => "'('",
+            TokenType::RightParen => "')'",
+            TokenType::LeftBrace => "'{'",
+            TokenType::RightBrace => "'}'",
+            TokenType::LeftBracket => "'['",
+            TokenType::RightBracket => "']'",
+            TokenType::Colon => "':'",
+            TokenType::Comma => "','",
+            TokenType::Dot => "'.'",
+            TokenType::Minus => "'-'",
+            TokenType::Plus => "'+'",
+            TokenType::Semicolon => "';'",
+            TokenType::Slash => "'/'",
+            TokenType::Star => "'*'",
+            TokenType::Bang => "'!'",
+            TokenType::BangEqual => "'!='",
+            TokenType::Equal => "'='",
+            TokenType::EqualEqual => "'=='",
+            TokenType::Greater => "'>'",
+            TokenType::GreaterEqual => "'>='",
+            TokenType::Less => "'<'",
+            TokenType::LessEqual => "'<='",
+            TokenType::LessLess => "'<<'",
+            TokenType::GreaterGreater => "'>>'",
+            TokenType::DotDotDot => "'...'",
+            TokenType::QuestionDot => "'?.'",
+            TokenType::Identifier => "identifier",
+            TokenType::String => "string",
+            TokenType::Number => "number",
+            TokenType::Newline => "line break",
+            TokenType::Comment => "comment",
+            TokenType::And => "'and'",
+            TokenType::Assert => "'assert'",
+            TokenType::Break => "'break'",
+            TokenType::Class => "'class'",
+            TokenType::Const => "'const'",
+            TokenType::Continue => "'continue'",
+            TokenType::Debug => "'debug'",
+            TokenType::Div => "'div'",
+            TokenType::Do => "'do'",
+            TokenType::Else => "'else'",
+            TokenType::Eprint => "'eprint'",
+            TokenType::False => "'false'",
+            TokenType::Fun => "'fun'",
+            TokenType::For => "'for'",
+            TokenType::If => "'if'",
+            TokenType::In => "'in'",
+            TokenType::Nil => "'nil'",
+            TokenType::Or => "'or'",
+            TokenType::Print => "'print'",
+            TokenType::Repeat => "'repeat'",
+            TokenType::Return => "'return'",
+            TokenType::Super => "'super'",
+            TokenType::This => "'this'",
+            TokenType::True => "'true'",
+            TokenType::Var => "'var'",
+            TokenType::While => "'while'",
+            TokenType::With => "'with'",
+            TokenType::Eof => "end of input",
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub enum Literal {
     Identifier(String),
@@ -65,10 +157,49 @@ pub struct Token {
     pub t_type: TokenType,
     pub lexeme: Vec<u8>,
     pub line: usize,
-    // pub col: f64,
+    /// 1-based column of the token's first character.
+    pub col: i64,
+    /// Byte offsets of the lexeme in the source, `start..end`.
+    pub start: usize,
+    pub end: usize,
     pub literal: Option<Literal>,
 }
 
+impl Token {
+    /// One JSON object, as written by `--emit-tokens-json`.
+    pub fn to_json(&self) -> String {
+        format!(
+            "{{ \"type\": \"{:?}\", \"lexeme\": \"{}\", \"line\": {}, \"col\": {}, \"start\": {}, \"end\": {} }}",
+            self.t_type,
+            error_format::escape_json(&String::from_utf8_lossy(&self.lexeme)),
+            self.line,
+            self.col,
+            self.start,
+            self.end
+        )
+    }
+}
+
+/// A lexical error such as an invalid character or an unterminated string.
+#[derive(Clone, Debug)]
+pub struct Error {
+    pub message: String,
+    pub line: usize,
+    pub col: i64,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} at line {}, column {}.",
+            self.message, self.line, self.col
+        )
+    }
+}
+
+impl std::error::Error for Error {}
+
 pub struct Scanner {
     source: Vec<u8>,
     tokens: Vec<Token>,
@@ -76,8 +207,15 @@ pub struct Scanner {
     current: usize,
     line: usize,
     col: i64,
-    error: Option<String>,
+    start_col: i64,
+    errors: Vec<Error>,
     keywords: HashMap<String, TokenType>,
+    /// Emit comments as `Comment` tokens instead of dropping them, for
+    /// tools such as a formatter. The parser doesn't accept them.
+    pub keep_comments: bool,
+    /// Emit a `Newline` token at every line break, which turns on
+    /// automatic semicolon insertion in the parser.
+    pub emit_newlines: bool,
 }
 
 impl Default for Scanner {
@@ -88,46 +226,85 @@ impl Default for Scanner {
             start: 0,
             current: 0,
             line: 1,
-            col: -1,
-            error: None,
+            col: 0,
+            start_col: 1,
+            errors: Vec::new(),
+            keep_comments: false,
+            emit_newlines: false,
             keywords: HashMap::from([
                 ("and".to_string(), TokenType::And),
+                ("assert".to_string(), TokenType::Assert),
+                ("break".to_string(), TokenType::Break),
                 ("class".to_string(), TokenType::Class),
+                ("const".to_string(), TokenType::Const),
+                ("continue".to_string(), TokenType::Continue),
+                ("debug".to_string(), TokenType::Debug),
+                ("div".to_string(), TokenType::Div),
+                ("do".to_string(), TokenType::Do),
                 ("else".to_string(), TokenType::Else),
+                ("eprint".to_string(), TokenType::Eprint),
                 ("false".to_string(), TokenType::False),
                 ("for".to_string(), TokenType::For),
                 ("fun".to_string(), TokenType::Fun),
                 ("if".to_string(), TokenType::If),
+                ("in".to_string(), TokenType::In),
                 ("nil".to_string(), TokenType::Nil),
                 ("or".to_string(), TokenType::Or),
                 ("print".to_string(), TokenType::Print),
+                ("repeat".to_string(), TokenType::Repeat),
                 ("return".to_string(), TokenType::Return),
                 ("super".to_string(), TokenType::Super),
                 ("this".to_string(), TokenType::This),
                 ("true".to_string(), TokenType::True),
                 ("var".to_string(), TokenType::Var),
                 ("while".to_string(), TokenType::While),
+                ("with".to_string(), TokenType::With),
             ]),
         }
     }
 }
 
-pub fn scan_tokens(input: String) -> Result<Vec<Token>, Error> {
-    let mut scanner: Scanner = Default::default();
+pub fn scan_tokens(input: String) -> Result<Vec<Token>, Vec<Error>> {
+    scan_with(Scanner::default(), input)
+}
+
+/// Like `scan_tokens`, but comments are kept as `Comment` tokens.
+pub fn scan_tokens_with_comments(input: String) -> Result<Vec<Token>, Vec<Error>> {
+    let scanner = Scanner {
+        keep_comments: true,
+        ..Default::default()
+    };
+    scan_with(scanner, input)
+}
 
+/// Like `scan_tokens`, but line breaks become `Newline` tokens.
+pub fn scan_tokens_with_newlines(input: String) -> Result<Vec<Token>, Vec<Error>> {
+    let scanner = Scanner {
+        emit_newlines: true,
+        ..Default::default()
+    };
+    scan_with(scanner, input)
+}
+
+fn scan_with(mut scanner: Scanner, input: String) -> Result<Vec<Token>, Vec<Error>> {
     scanner.scan_tokens(input);
 
-    Ok(scanner.tokens)
+    if scanner.errors.is_empty() {
+        Ok(scanner.tokens)
+    } else {
+        Err(scanner.errors)
+    }
 }
 
 impl Scanner {
-    pub fn new(source: String) -> Self {
+    pub fn new(_source: String) -> Self {
         Scanner::default()
     }
     pub fn scan_tokens(&mut self, source: String) {
         self.source = source.into_bytes();
         while !self.is_at_end() {
             self.start = self.current;
+            self.start_col = self.col + 1;
             self.scan_token();
         }
 
@@ -135,10 +312,32 @@ impl Scanner {
             t_type: TokenType::Eof,
             lexeme: vec![],
             line: self.line,
+            col: self.col + 1,
+            start: self.source.len(),
+            end: self.source.len(),
             literal: None,
         });
     }
 
+    /// How many tokens the last scan produced, not counting the final `Eof`.
+    pub fn token_count(&self) -> usize {
+        self.tokens
+            .iter()
+            .filter(|token| token.t_type != TokenType::Eof)
+            .count()
+    }
+
+    /// How many lines the scanned source has. A trailing newline doesn't
+    /// start another line, so this matches `str::lines`.
+    pub fn line_count(&self) -> usize {
+        let newlines = self.source.iter().filter(|&&b| b == b'\n').count();
+        if self.source.is_empty() || self.source.ends_with(b"\n") {
+            newlines
+        } else {
+            newlines + 1
+        }
+    }
+
     fn scan_token(&mut self) {
         let c = self.advance();
         match c {
@@ -146,8 +345,23 @@ impl Scanner {
             ')' => self.add_token(TokenType::RightParen),
             '{' => self.add_token(TokenType::LeftBrace),
             '}' => self.add_token(TokenType::RightBrace),
+            '[' => self.add_token(TokenType::LeftBracket),
+            ']' => self.add_token(TokenType::RightBracket),
             ',' => self.add_token(TokenType::Comma),
-            '.' => self.add_token(TokenType::Dot),
+            ':' => self.add_token(TokenType::Colon),
+            '?' if self.peek() == '.' => {
+                self.advance();
+                self.add_token(TokenType::QuestionDot)
+            }
+            '.' => {
+                if self.peek() == '.' && self.peek_next() == '.' {
+                    self.advance();
+                    self.advance();
+                    self.add_token(TokenType::DotDotDot)
+                } else {
+                    self.add_token(TokenType::Dot)
+                }
+            }
             '-' => self.add_token(TokenType::Minus),
             '+' => self.add_token(TokenType::Plus),
             '*' => self.add_token(TokenType::Star),
@@ -162,6 +376,9 @@ impl Scanner {
             }
             ' ' | '\r' | '\t' => {}
             '\n' => {
+                if self.emit_newlines {
+                    self.add_token(TokenType::Newline);
+                }
                 self.line += 1;
                 self.col = 0;
             }
@@ -175,26 +392,35 @@ impl Scanner {
                 })
             }
             '<' => {
-                let match_eq = self.matches('=');
-                self.add_token(if match_eq {
+                let token_type = if self.matches('=') {
                     TokenType::LessEqual
+                } else if self.matches('<') {
+                    TokenType::LessLess
                 } else {
                     TokenType::Less
-                })
+                };
+                self.add_token(token_type)
             }
             '>' => {
-                let match_eq = self.matches('=');
-                self.add_token(if match_eq {
+                let token_type = if self.matches('=') {
                     TokenType::GreaterEqual
+                } else if self.matches('>') {
+                    TokenType::GreaterGreater
                 } else {
                     TokenType::Greater
-                })
+                };
+                self.add_token(token_type)
             }
             '/' => {
                 if self.matches('/') {
                     while self.peek() != '\n' && !self.is_at_end() {
                         self.advance();
                     }
+                    if self.keep_comments {
+                        self.add_token(TokenType::Comment);
+                    }
+                } else if self.matches('*') {
+                    self.block_comment();
                 } else {
                     self.add_token(TokenType::Slash);
                 }
@@ -205,16 +431,25 @@ impl Scanner {
                 } else if Self::is_alpha(c) {
                     self.identifier();
                 } else {
-                    let error = format!("Invalid character: {}", c);
-                    self.error = Some(error.clone());
-                    format_error(&error, self.line, self.col);
+                    // Take the rest of a multi-byte character so it is
+                    // reported once and in one piece.
+                    while (self.peek() as u32) & 0xc0 == 0x80 {
+                        self.advance();
+                    }
+                    let error = format!(
+                        "Invalid character: {}",
+                        String::from_utf8_lossy(&self.source[self.start..self.current])
+                    );
+                    self.report_error(&error);
                 }
             }
         }
     }
 
+    /// Source bytes are looked at one at a time, so only ASCII letters can
+    /// start an identifier; a UTF-8 lead byte would split its character.
     fn is_alpha(c: char) -> bool {
-        c.is_alphabetic() || c == '_'
+        c.is_ascii_alphabetic() || c == '_'
     }
 
     fn is_ascii_digit(c: char) -> bool {
@@ -231,21 +466,30 @@ impl Scanner {
             self.advance();
         }
         let literal = String::from_utf8(self.source[self.start..self.current].to_vec()).unwrap();
-        // let token_type = self.keywords.get(&literal)
-        //     .copied()  // 或者 .cloned()
-        //     .unwrap_or(TokenType::Identifier);
-        let token_type = if self.keywords.contains_key(&literal) {
-            *self.keywords.get(&literal).unwrap()
-        } else {
-            TokenType::Identifier
-        };
-        self.add_token(token_type); // why not add_token_literal ?
+        match self.keywords.get(&literal) {
+            Some(keyword) => self.add_token(*keyword),
+            None => {
+                self.add_token_literal(TokenType::Identifier, Some(Literal::Identifier(literal)))
+            }
+        }
     }
 
     fn number(&mut self) {
+        let leading_zero = self.source[self.start] == b'0';
+        if leading_zero && self.peek() == 'o' {
+            self.advance();
+            return self.octal_number();
+        }
         while Self::is_ascii_digit(self.peek()) {
             self.advance();
         }
+        // `012` is octal in C, so refuse to guess what it means here.
+        if leading_zero && self.current - self.start > 1 {
+            self.report_error(
+                "Leading zeros are not allowed in numbers, use the 0o prefix for octal.",
+            );
+            return;
+        }
         if self.peek() == '.' && Self::is_ascii_digit(self.peek_next()) {
             self.advance();
             while Self::is_ascii_digit(self.peek()) {
@@ -258,17 +502,65 @@ impl Scanner {
             .unwrap();
         self.add_token_literal(TokenType::Number, Some(Literal::Number(digit)));
     }
+    fn octal_number(&mut self) {
+        while Self::is_ascii_digit(self.peek()) {
+            self.advance();
+        }
+        let digits = String::from_utf8(self.source[self.start + 2..self.current].to_vec()).unwrap();
+        match i64::from_str_radix(&digits, 8) {
+            Ok(n) => self.add_token_literal(TokenType::Number, Some(Literal::Number(n as f64))),
+            Err(_) => {
+                let error = format!("Invalid octal literal: 0o{}", digits);
+                self.report_error(&error);
+            }
+        }
+    }
+
+    /// `/* ... */`, which may span lines but doesn't nest.
+    fn block_comment(&mut self) {
+        let start_line = self.line;
+        while !(self.peek() == '*' && self.peek_next() == '/') {
+            if self.is_at_end() {
+                self.errors.push(Error {
+                    message: "Unterminated block comment.".to_string(),
+                    line: start_line,
+                    col: self.start_col,
+                });
+                return;
+            }
+            if self.advance() == '\n' {
+                self.line += 1;
+                self.col = 0;
+            }
+        }
+        self.advance();
+        self.advance();
+        if self.keep_comments {
+            let end_line = self.line;
+            // A comment token sits where the comment starts.
+            self.line = start_line;
+            self.add_token(TokenType::Comment);
+            self.line = end_line;
+        }
+    }
+
+    fn report_error(&mut self, error: &str) {
+        self.errors.push(Error {
+            message: error.to_string(),
+            line: self.line,
+            col: self.start_col,
+        });
+    }
+
     fn string(&mut self) {
         while self.peek() != '"' && !self.is_at_end() {
-            if self.peek() == '\n' {
+            if self.advance() == '\n' {
                 self.line += 1;
+                self.col = 0;
             }
-            self.advance();
         }
         if self.is_at_end() {
-            let error = format!("Unterminated string.");
-            self.error = Some(error.clone());
-            format_error(&error, self.line, self.col);
+            self.report_error("Unterminated string.");
             return;
         }
         self.advance();
@@ -285,7 +577,8 @@ impl Scanner {
             return false;
         }
         self.current += 1;
-        return true;
+        self.col += 1;
+        true
     }
 
     fn peek(&self) -> char {
@@ -299,12 +592,13 @@ impl Scanner {
         if self.current + 1 >= self.source.len() {
             return '\0';
         }
-        return self.source[self.current + 1] as char;
+        self.source[self.current + 1] as char
     }
 
     fn advance(&mut self) -> char {
         let c = self.source[self.current];
         self.current += 1;
+        self.col += 1;
         // c as char
         char::from(c)
     }
@@ -318,6 +612,9 @@ impl Scanner {
             t_type: token_type,
             literal,
             line: self.line,
+            col: self.start_col,
+            start: self.start,
+            end: self.current,
             lexeme: self.source[self.start..self.current].to_vec(),
         });
     }
@@ -326,3 +623,85 @@ impl Scanner {
         self.current >= self.source.len()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(source: &str) -> Vec<Token> {
+        scan_tokens(source.to_string()).unwrap()
+    }
+
+    #[test]
+    fn two_character_operators_start_at_their_first_character() {
+        let tokens = tokens("a >= b");
+        assert_eq!(tokens[1].t_type, TokenType::GreaterEqual);
+        assert_eq!((tokens[1].line, tokens[1].col), (1, 3));
+        assert_eq!(tokens[2].col, 6);
+    }
+
+    #[test]
+    fn octal_literals_and_leading_zeros() {
+        assert!(matches!(tokens("0o17")[0].literal, Some(Literal::Number(n)) if n == 15.0));
+        let errors = scan_tokens("012".to_string()).unwrap_err();
+        assert_eq!(
+            errors[0].message,
+            "Leading zeros are not allowed in numbers, use the 0o prefix for octal."
+        );
+    }
+
+    #[test]
+    fn columns_restart_after_a_multi_line_string() {
+        let errors = crate::parse("print \"ab\ncd\" x;".to_string()).unwrap_err();
+        assert_eq!((errors[0].line, errors[0].col), (2, 5));
+    }
+
+    #[test]
+    fn a_number_or_identifier_can_end_the_source() {
+        let scanned = tokens("x = 12.5");
+        assert!(matches!(scanned[2].literal, Some(Literal::Number(n)) if n == 12.5));
+        assert_eq!(scanned[3].t_type, TokenType::Eof);
+        assert_eq!(tokens("abc")[0].lexeme, b"abc");
+    }
+
+    #[test]
+    fn comments_can_be_kept_as_tokens() {
+        let source = "// first\nvar x = 1; /* second\nspans */ print x;";
+        let comments: Vec<_> = scan_tokens_with_comments(source.to_string())
+            .unwrap()
+            .into_iter()
+            .filter(|token| token.t_type == TokenType::Comment)
+            .map(|token| (String::from_utf8(token.lexeme).unwrap(), token.line))
+            .collect();
+        assert_eq!(
+            comments,
+            [
+                ("// first".to_string(), 1),
+                ("/* second\nspans */".to_string(), 2)
+            ]
+        );
+        assert!(tokens(source)
+            .iter()
+            .all(|token| token.t_type != TokenType::Comment));
+    }
+
+    #[test]
+    fn the_scanner_counts_tokens_and_lines() {
+        let mut scanner = Scanner::default();
+        scanner.scan_tokens("var x = 1;\nprint x;".to_string());
+        assert_eq!((scanner.token_count(), scanner.line_count()), (8, 2));
+        let mut scanner = Scanner::default();
+        scanner.scan_tokens("print 1;\n".to_string());
+        assert_eq!((scanner.token_count(), scanner.line_count()), (3, 1));
+    }
+
+    #[test]
+    fn tokens_carry_their_byte_offsets() {
+        let scanned = tokens("print \"é\"; x");
+        let offsets: Vec<_> = scanned
+            .iter()
+            .map(|token| (token.start, token.end))
+            .collect();
+        assert_eq!(offsets, [(0, 5), (6, 10), (10, 11), (12, 13), (13, 13)]);
+    }
+}
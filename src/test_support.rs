@@ -0,0 +1,57 @@
+//! Helpers for the unit tests: run a script the way `lox` does and get
+//! back what it printed.
+
+use crate::error_format;
+use crate::resolver::Lints;
+use crate::tree_interpreter::{Interpreter, SharedBuffer};
+
+/// An interpreter whose `print` output goes to the returned buffer.
+pub fn interpreter() -> (Interpreter, SharedBuffer) {
+    let mut interpreter = Interpreter::default();
+    let output = SharedBuffer::default();
+    interpreter.output = Box::new(output.clone());
+    (interpreter, output)
+}
+
+/// Parses, checks and runs `source` in `interpreter`. On failure, the
+/// message of the first error.
+pub fn run_in(interpreter: &mut Interpreter, source: &str) -> Result<(), String> {
+    let program = crate::parse(source.to_string()).map_err(|errors| errors[0].message.clone())?;
+    let program = crate::check_program(program, Lints::default())
+        .map_err(|errors| errors[0].message.clone())?;
+    interpreter.interpret(&program).map_err(|err| err.message)
+}
+
+/// Runs `source` in a fresh interpreter and returns what it printed.
+pub fn run(source: &str) -> Result<String, String> {
+    let (mut interpreter, output) = interpreter();
+    run_in(&mut interpreter, source)?;
+    Ok(output.contents())
+}
+
+/// Runs `f` on a thread with a stack as big as the one `lox` gives
+/// scripts, for tests that recurse deeply.
+pub fn on_big_stack<T, F>(f: F) -> T
+where
+    T: Send + 'static,
+    F: FnOnce() -> T + Send + 'static,
+{
+    std::thread::Builder::new()
+        .stack_size(512 * 1024 * 1024)
+        .spawn(f)
+        .unwrap()
+        .join()
+        .unwrap()
+}
+
+/// The warnings the static checks raise on `source`, as the JSON array
+/// `--json-errors` would print.
+pub fn warnings(source: &str, lints: Lints) -> String {
+    error_format::set_json_errors(true);
+    let program = crate::parse(source.to_string()).unwrap();
+    let checked = crate::check_program(program, lints);
+    let warnings = error_format::take_pending();
+    error_format::set_json_errors(false);
+    assert!(checked.is_ok(), "{} has errors", source);
+    warnings
+}
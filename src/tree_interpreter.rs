@@ -1,16 +1,354 @@
+use crate::ast_printer;
 use crate::expr::{
-    self, BinaryOp, Expr, Literal, SourceLocation, Stmt, Symbol, UnaryOp, UnaryOpType,
+    self, BinaryOp, Expr, ExprVisitor, FunDecl, Literal, LogicalOp, SourceLocation, Span, Stmt,
+    StmtVisitor, Symbol, UnaryOp, UnaryOpType,
 };
+use crate::natives::{self, Clock, Rng, SystemClock};
 use core::f64;
+use std::cell::RefCell;
 use std::collections::HashMap;
-use std::time::SystemTime;
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Write};
+use std::rc::Rc;
 
-#[derive(Debug)]
+/// Default for `Interpreter::max_call_depth`. A Lox call takes a few
+/// kilobytes of native stack, about 20 KB in a debug build, so this many
+/// fit in the 2 MB a spawned thread gets. `lox` raises it together with
+/// the stack it runs scripts on (see `main.rs`).
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 64;
+
+/// How far `==` follows lists and maps nested in each other.
+const MAX_EQUALITY_DEPTH: usize = 256;
+
+#[derive(Debug, Clone)]
 pub enum Value {
     Number(f64),
     String(String),
     Boolean(bool),
     Nil,
+    LoxFunction(Rc<LoxFunction>),
+    NativeFunction(NativeFunction),
+    /// A native method looked up on a value, e.g. `"abc".upper`. The
+    /// receiver is passed to the native as its first argument.
+    BoundMethod(Box<Value>, NativeFunction),
+    List(Rc<RefCell<Vec<Value>>>),
+    Bytes(Vec<u8>),
+    Map(Rc<RefCell<LoxMap>>),
+    Range(Range),
+}
+
+/// The numbers `range(start, end, step)` counts through, produced one at a
+/// time rather than stored. `end` itself is never reached.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Range {
+    pub start: f64,
+    pub end: f64,
+    pub step: f64,
+}
+
+impl Range {
+    /// How many numbers the range yields; none if `step` heads away from
+    /// `end`.
+    pub fn len(&self) -> usize {
+        let steps = (self.end - self.start) / self.step;
+        if steps > 0.0 {
+            steps.ceil() as usize
+        } else {
+            0
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Each number is computed from its position, so rounding errors in
+    /// `step` don't pile up over a long range.
+    pub fn iter(&self) -> impl Iterator<Item = f64> {
+        let Range { start, step, .. } = *self;
+        (0..self.len()).map(move |i| start + i as f64 * step)
+    }
+}
+
+/// The entries of a map in insertion order, so `keys()` comes out the same
+/// on every run. Setting a key that is already there keeps its position.
+#[derive(Debug, Default)]
+pub struct LoxMap {
+    entries: Vec<(Value, Value)>,
+}
+
+impl LoxMap {
+    pub fn get(&self, key: &Value) -> Option<&Value> {
+        self.entries
+            .iter()
+            .find(|(k, _)| Interpreter::equals(k, key))
+            .map(|(_, v)| v)
+    }
+
+    pub fn set(&mut self, key: Value, value: Value) {
+        match self
+            .entries
+            .iter_mut()
+            .find(|(k, _)| Interpreter::equals(k, &key))
+        {
+            Some((_, v)) => *v = value,
+            None => self.entries.push((key, value)),
+        }
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &Value> {
+        self.entries.iter().map(|(k, _)| k)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+/// Same as Lox's `==`.
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        Interpreter::equals(self, other)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(n: f64) -> Self {
+        Value::Number(n)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Self {
+        Value::Boolean(b)
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Self {
+        Value::String(s)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Self {
+        Value::String(s.to_string())
+    }
+}
+
+/// The conversion error names the type that was found instead. It is a
+/// `String` like a native's own errors, so a native can pass it on with `?`.
+fn mismatch(expected: LoxType, found: &Value) -> String {
+    format!("expected a {}, got {}", expected, instance_of(found))
+}
+
+impl TryFrom<Value> for f64 {
+    type Error = String;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::Number(n) => Ok(n),
+            _ => Err(mismatch(LoxType::Number, &value)),
+        }
+    }
+}
+
+impl TryFrom<Value> for bool {
+    type Error = String;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::Boolean(b) => Ok(b),
+            _ => Err(mismatch(LoxType::Boolean, &value)),
+        }
+    }
+}
+
+impl TryFrom<Value> for String {
+    type Error = String;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::String(s) => Ok(s),
+            _ => Err(mismatch(LoxType::String, &value)),
+        }
+    }
+}
+
+/// Numbers are normalized so IEEE artifacts read naturally: `-0` prints
+/// as `0`, infinities as `inf` / `-inf` and NaN as `nan`.
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fmt_with(f, NumberFormat::default())
+    }
+}
+
+/// The interpreter's settings for printing numbers.
+#[derive(Clone, Copy, Default)]
+struct NumberFormat {
+    scientific: Option<ScientificThresholds>,
+    float_precision: Option<usize>,
+}
+
+/// A value printed with the interpreter's number settings.
+struct Formatted<'a> {
+    value: &'a Value,
+    numbers: NumberFormat,
+}
+
+impl fmt::Display for Formatted<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.value.fmt_with(f, self.numbers)
+    }
+}
+
+impl Value {
+    /// The value as a count or position: a whole number that is not
+    /// negative. The error, for the caller to say what the number was for,
+    /// is the same wherever one is needed.
+    pub fn as_index(&self) -> Result<usize, String> {
+        match self {
+            // The bound leaves out NaN and the infinities too.
+            Value::Number(n) if n.fract() == 0.0 && (0.0..usize::MAX as f64).contains(n) => {
+                Ok(*n as usize)
+            }
+            Value::Number(n) => Err(format!(
+                "expected a non-negative integer, got {}",
+                format_number(*n, NumberFormat::default())
+            )),
+            val => Err(format!(
+                "expected a non-negative integer, got {}",
+                instance_of(val)
+            )),
+        }
+    }
+
+    fn fmt_with(&self, f: &mut fmt::Formatter<'_>, numbers: NumberFormat) -> fmt::Result {
+        self.fmt_nested(f, numbers, &mut Vec::new())
+    }
+
+    /// `open` holds the lists and maps being printed around this value. One
+    /// that contains itself prints as `[...]` or `{...}` where it recurs.
+    fn fmt_nested(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+        numbers: NumberFormat,
+        open: &mut Vec<*const ()>,
+    ) -> fmt::Result {
+        match self {
+            Value::Number(n) => write!(f, "{}", format_number(*n, numbers)),
+            Value::String(s) => write!(f, "{}", s),
+            Value::Nil => write!(f, "nil"),
+            Value::Boolean(b) => write!(f, "{}", b),
+            Value::LoxFunction(fun) => write!(f, "{:?}", fun),
+            Value::NativeFunction(native) | Value::BoundMethod(_, native) => {
+                write!(f, "{:?}", native)
+            }
+            Value::List(elements) => {
+                let id = Rc::as_ptr(elements) as *const ();
+                if open.contains(&id) {
+                    return write!(f, "[...]");
+                }
+                open.push(id);
+                write!(f, "[")?;
+                for (i, element) in elements.borrow().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    element.fmt_nested(f, numbers, open)?;
+                }
+                open.pop();
+                write!(f, "]")
+            }
+            Value::Bytes(bytes) => {
+                write!(f, "<{} bytes:", bytes.len())?;
+                for byte in bytes.iter().take(BYTES_PREVIEW_LEN) {
+                    write!(f, " {:02x}", byte)?;
+                }
+                if bytes.len() > BYTES_PREVIEW_LEN {
+                    write!(f, " ...")?;
+                }
+                write!(f, ">")
+            }
+            Value::Map(map) => {
+                let id = Rc::as_ptr(map) as *const ();
+                if open.contains(&id) {
+                    return write!(f, "{{...}}");
+                }
+                open.push(id);
+                write!(f, "{{")?;
+                for (i, (key, value)) in map.borrow().entries.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    key.fmt_nested(f, numbers, open)?;
+                    write!(f, ": ")?;
+                    value.fmt_nested(f, numbers, open)?;
+                }
+                open.pop();
+                write!(f, "}}")
+            }
+            Value::Range(range) => {
+                let number = |n| format_number(n, numbers);
+                write!(f, "range({}, {}", number(range.start), number(range.end))?;
+                if range.step != 1.0 {
+                    write!(f, ", {}", number(range.step))?;
+                }
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+/// How many bytes a printed bytes value shows before it is cut short.
+const BYTES_PREVIEW_LEN: usize = 16;
+
+/// When numbers switch to exponential form such as `1e21`: non-zero
+/// magnitudes at or above `upper`, or below `lower`, are printed that way.
+#[derive(Debug, Clone, Copy)]
+pub struct ScientificThresholds {
+    pub upper: f64,
+    pub lower: f64,
+}
+
+/// The thresholds JavaScript uses.
+impl Default for ScientificThresholds {
+    fn default() -> Self {
+        ScientificThresholds {
+            upper: 1e21,
+            lower: 1e-6,
+        }
+    }
+}
+
+fn format_number(n: f64, numbers: NumberFormat) -> String {
+    let n = match numbers.float_precision {
+        // Rounding through the exponential form keeps significant digits
+        // rather than decimal places, so 0.000123 doesn't become 0.
+        Some(digits) if n.is_finite() && n.fract() != 0.0 => {
+            format!("{:.*e}", digits.max(1) - 1, n).parse().unwrap_or(n)
+        }
+        _ => n,
+    };
+    if n.is_nan() {
+        "nan".to_string()
+    } else if n.is_infinite() {
+        if n > 0.0 { "inf" } else { "-inf" }.to_string()
+    } else if n == 0.0 {
+        // Covers -0.0 as well.
+        "0".to_string()
+    } else if numbers
+        .scientific
+        .is_some_and(|t| n.abs() >= t.upper || n.abs() < t.lower)
+    {
+        format!("{:e}", n)
+    } else {
+        format!("{}", n)
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -19,6 +357,28 @@ pub enum LoxType {
     String,
     Boolean,
     Nil,
+    Function,
+    List,
+    Bytes,
+    Map,
+    Range,
+}
+
+impl fmt::Display for LoxType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            LoxType::Number => "number",
+            LoxType::String => "string",
+            LoxType::Boolean => "boolean",
+            LoxType::Nil => "nil",
+            LoxType::Function => "function",
+            LoxType::List => "list",
+            LoxType::Bytes => "bytes",
+            LoxType::Map => "map",
+            LoxType::Range => "range",
+        };
+        write!(f, "{}", name)
+    }
 }
 
 pub fn instance_of(val: &Value) -> LoxType {
@@ -27,164 +387,1353 @@ pub fn instance_of(val: &Value) -> LoxType {
         Value::Number(_) => LoxType::Number,
         Value::String(_) => LoxType::String,
         Value::Boolean(_) => LoxType::Boolean,
+        Value::LoxFunction(_) | Value::NativeFunction(_) | Value::BoundMethod(..) => {
+            LoxType::Function
+        }
+        Value::List(_) => LoxType::List,
+        Value::Bytes(_) => LoxType::Bytes,
+        Value::Map(_) => LoxType::Map,
+        Value::Range(_) => LoxType::Range,
+    }
+}
+
+/// The Rust side of a native. Errors are reported without a location; the
+/// call site appends it.
+pub type NativeFn = dyn Fn(&mut Interpreter, &[Value]) -> Result<Value, String>;
+
+/// How many arguments a callable takes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Arity {
+    Exactly(usize),
+    /// Variadic, with this many required arguments.
+    AtLeast(usize),
+    /// Optional trailing arguments, from the first count to the second.
+    Between(usize, usize),
+}
+
+impl Arity {
+    pub fn accepts(self, count: usize) -> bool {
+        match self {
+            Arity::Exactly(n) => count == n,
+            Arity::AtLeast(n) => count >= n,
+            Arity::Between(min, max) => (min..=max).contains(&count),
+        }
+    }
+}
+
+/// Reads as the middle of "expected ... but got 2".
+impl fmt::Display for Arity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (prefix, n) = match self {
+            Arity::Exactly(n) => (String::new(), n),
+            Arity::AtLeast(n) => ("at least ".to_string(), n),
+            Arity::Between(min, max) => (format!("{} to ", min), max),
+        };
+        write!(
+            f,
+            "{}{} argument{}",
+            prefix,
+            n,
+            if *n == 1 { "" } else { "s" }
+        )
+    }
+}
+
+#[derive(Clone)]
+pub struct NativeFunction {
+    pub name: String,
+    pub arity: Arity,
+    pub callable: Rc<NativeFn>,
+}
+
+impl fmt::Debug for NativeFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<native fn {}>", self.name)
+    }
+}
+
+pub struct LoxFunction {
+    pub decl: FunDecl,
+    pub closure: Rc<RefCell<Environment>>,
+}
+
+impl LoxFunction {
+    pub fn arity(&self) -> Arity {
+        match self.decl.rest {
+            Some(_) => Arity::AtLeast(self.decl.params.len()),
+            None => Arity::Exactly(self.decl.params.len()),
+        }
+    }
+}
+
+impl fmt::Debug for LoxFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<fn {}>", self.decl.name.name)
+    }
+}
+
+/// A failure while running a program, with the source location it
+/// happened at.
+#[derive(Debug, Clone)]
+pub struct RuntimeError {
+    pub message: String,
+    pub line: usize,
+    pub col: i64,
+    /// Extra context shown on its own line after the location.
+    pub note: Option<String>,
+    /// Byte offsets of the failing expression in the source, for editors
+    /// to underline. Only operator errors carry one so far.
+    pub span: Option<Span>,
+    /// The variables in scope at the failing statement, one per line, when
+    /// `Interpreter::dump_env_on_error` is set. Not part of `Display`.
+    pub bindings: Option<String>,
+}
+
+impl RuntimeError {
+    pub fn new(message: impl Into<String>, line: usize, col: i64) -> Self {
+        RuntimeError {
+            message: message.into(),
+            line,
+            col,
+            note: None,
+            span: None,
+            bindings: None,
+        }
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.note = Some(note.into());
+        self
+    }
+
+    pub fn with_span(mut self, span: Span) -> Self {
+        self.span = Some(span);
+        self
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} at line {}, column {}.",
+            self.message, self.line, self.col
+        )?;
+        if let Some(note) = &self.note {
+            write!(f, "\nNote: {}", note)?;
+        }
+        Ok(())
+    }
+}
+
+/// The candidate fewest edits away from `name`, allowing one edit per three
+/// characters. Names shorter than three characters get no suggestion: most
+/// other short names are an edit or two from them.
+pub(crate) fn closest_name(
+    name: &str,
+    candidates: impl IntoIterator<Item = String>,
+) -> Option<String> {
+    if name.chars().count() < 3 {
+        return None;
+    }
+    let limit = name.chars().count() / 3;
+    candidates
+        .into_iter()
+        .map(|candidate| (edit_distance(name, &candidate), candidate))
+        .filter(|(distance, _)| *distance <= limit)
+        .min()
+        .map(|(_, candidate)| candidate)
+}
+
+/// The Levenshtein distance: how many single-character insertions,
+/// deletions or substitutions turn `a` into `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
     }
+    previous[b.len()]
 }
 
-pub enum RuntimeError {}
 pub enum TypeError {}
 pub enum NameError {}
 pub enum ReferenceError {}
 
-pub enum LookupResult<'a> {
-    Ok(&'a Value),
+pub enum LookupResult {
+    Ok(Value),
     DeclaredNotDefined(SourceLocation),
     NotDeclared,
 }
 
-struct Environment {
-    values: HashMap<String, (Option<Value>, SourceLocation)>,
+#[derive(Clone)]
+struct Binding {
+    value: Option<Value>,
+    source_loc: SourceLocation,
+    /// Functions and `const` declarations can't be reassigned, `var` bindings can.
+    mutable: bool,
+}
+
+/// The state saved by `Environment::snapshot`.
+pub struct EnvSnapshot {
+    values: HashMap<String, Binding>,
+    names: Vec<String>,
+}
+
+#[derive(Default)]
+pub struct Environment {
+    enclosing: Option<Rc<RefCell<Environment>>>,
+    values: HashMap<String, Binding>,
+    /// Binding names in declaration order.
+    names: Vec<String>,
 }
 
 impl Environment {
     pub fn new() -> Self {
         Environment {
+            enclosing: None,
+            values: HashMap::new(),
+            names: Vec::new(),
+        }
+    }
+
+    pub fn with_enclosing(enclosing: Rc<RefCell<Environment>>) -> Self {
+        Environment {
+            enclosing: Some(enclosing),
             values: HashMap::new(),
+            names: Vec::new(),
         }
     }
 
     pub fn define(&mut self, symbol: Symbol, value: Option<Value>) {
+        self.insert(symbol, value, true);
+    }
+
+    pub fn define_immutable(&mut self, symbol: Symbol, value: Value) {
+        self.insert(symbol, Some(value), false);
+    }
+
+    /// Records the bindings declared directly in this environment, for
+    /// `restore` to go back to.
+    pub fn snapshot(&self) -> EnvSnapshot {
+        EnvSnapshot {
+            values: self.values.clone(),
+            names: self.names.clone(),
+        }
+    }
+
+    /// Puts the bindings back as they were at `snapshot`: names declared
+    /// since are gone and reassigned ones get their old values. Only the
+    /// bindings are restored, so a list changed in place stays changed.
+    pub fn restore(&mut self, snapshot: EnvSnapshot) {
+        self.values = snapshot.values;
+        self.names = snapshot.names;
+    }
+
+    /// Names declared directly in this environment, oldest first.
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    fn insert(&mut self, symbol: Symbol, value: Option<Value>, mutable: bool) {
+        if !self.values.contains_key(&symbol.name) {
+            self.names.push(symbol.name.clone());
+        }
         self.values.insert(
             symbol.name,
-            (
+            Binding {
                 value,
-                SourceLocation {
+                source_loc: SourceLocation {
                     line: symbol.line,
                     col: symbol.col,
                 },
-            ),
+                mutable,
+            },
         );
     }
 
     pub fn lookup(&self, symbol: &Symbol) -> LookupResult {
         match self.values.get(&symbol.name) {
             // why loc is unknown ?
-            Some((value, source_loc)) => match value {
-                Some(val) => LookupResult::Ok(val),
+            Some(Binding {
+                value, source_loc, ..
+            }) => match value {
+                Some(val) => LookupResult::Ok(val.clone()),
                 None => LookupResult::DeclaredNotDefined(SourceLocation {
                     line: source_loc.line,
                     col: source_loc.col,
                 }),
             },
-            None => LookupResult::NotDeclared,
+            None => match &self.enclosing {
+                Some(enclosing) => enclosing.borrow().lookup(symbol),
+                None => LookupResult::NotDeclared,
+            },
         }
     }
 
-    pub fn get(&self, symbol: &Symbol) -> Result<&Value, String> {
-        match self.lookup(symbol) {
-            LookupResult::Ok(val) => Ok(val),
-            LookupResult::DeclaredNotDefined(source_loc) => Err(format!(
-                "Use undefined variable '{}' in line {}, column {}.\
-                \nNote: {} was declared at line {}, column {} but not defined.",
-                symbol.name, symbol.line, symbol.col, symbol.name, source_loc.line, source_loc.col
+    pub fn assign(&mut self, symbol: &Symbol, value: &Value) -> Result<(), RuntimeError> {
+        if let Some(binding) = self.values.get_mut(&symbol.name) {
+            if !binding.mutable {
+                return Err(RuntimeError::new(
+                    format!("Cannot assign to immutable binding '{}'", symbol.name),
+                    symbol.line,
+                    symbol.col,
+                )
+                .with_note(format!(
+                    "{} was declared at line {}, column {}.",
+                    symbol.name, binding.source_loc.line, binding.source_loc.col
+                )));
+            }
+            binding.value = Some(value.clone());
+            return Ok(());
+        }
+        match &self.enclosing {
+            Some(enclosing) => enclosing.borrow_mut().assign(symbol, value),
+            None => Err(RuntimeError::new(
+                format!("Assign to undeclared variable '{}'", symbol.name),
+                symbol.line,
+                symbol.col,
             )),
-            LookupResult::NotDeclared => Err(format!(
-                "Use undefined variable '{}' in line {}, column {}.",
-                symbol.name, symbol.line, symbol.col
+        }
+    }
+
+    /// An undeclared name gets a note suggesting the closest visible one,
+    /// if any is a likely typo of it.
+    pub fn get(&self, symbol: &Symbol) -> Result<Value, RuntimeError> {
+        let result = self.lookup(symbol);
+        let not_declared = matches!(result, LookupResult::NotDeclared);
+        Self::found(symbol, result).map_err(|err| {
+            match closest_name(&symbol.name, self.visible_names()) {
+                Some(name) if not_declared => err.with_note(format!("Did you mean '{}'?", name)),
+                _ => err,
+            }
+        })
+    }
+
+    /// Every name visible from here, innermost scope first.
+    fn visible_names(&self) -> Vec<String> {
+        let mut names = self.names.clone();
+        if let Some(enclosing) = &self.enclosing {
+            names.extend(enclosing.borrow().visible_names());
+        }
+        names
+    }
+
+    /// Like `get`, but reads the binding exactly `depth` environments out
+    /// instead of searching for it, for names the resolver has placed.
+    pub fn get_at(&self, depth: usize, symbol: &Symbol) -> Result<Value, RuntimeError> {
+        if depth > 0 {
+            return match &self.enclosing {
+                Some(enclosing) => enclosing.borrow().get_at(depth - 1, symbol),
+                None => Err(Self::missing_at(symbol)),
+            };
+        }
+        debug_assert!(
+            self.values.contains_key(&symbol.name),
+            "'{}' is not bound at the resolved depth",
+            symbol.name
+        );
+        let result = match self.values.get(&symbol.name) {
+            Some(Binding {
+                value: Some(val), ..
+            }) => LookupResult::Ok(val.clone()),
+            Some(Binding { source_loc, .. }) => {
+                LookupResult::DeclaredNotDefined(source_loc.clone())
+            }
+            None => LookupResult::NotDeclared,
+        };
+        Self::found(symbol, result)
+    }
+
+    /// Like `assign`, but writes the binding exactly `depth` environments
+    /// out.
+    pub fn assign_at(
+        &mut self,
+        depth: usize,
+        symbol: &Symbol,
+        value: &Value,
+    ) -> Result<(), RuntimeError> {
+        if depth > 0 {
+            return match &self.enclosing {
+                Some(enclosing) => enclosing.borrow_mut().assign_at(depth - 1, symbol, value),
+                None => Err(Self::missing_at(symbol)),
+            };
+        }
+        debug_assert!(
+            self.values.contains_key(&symbol.name),
+            "'{}' is not bound at the resolved depth",
+            symbol.name
+        );
+        match self.values.get(&symbol.name) {
+            // `assign` finds the binding right here and reports immutability.
+            Some(_) => self.assign(symbol, value),
+            None => Err(RuntimeError::new(
+                format!("Assign to undeclared variable '{}'", symbol.name),
+                symbol.line,
+                symbol.col,
             )),
         }
     }
+
+    fn missing_at(symbol: &Symbol) -> RuntimeError {
+        debug_assert!(false, "'{}' resolved past the outermost scope", symbol.name);
+        RuntimeError::new(
+            format!("Use undefined variable '{}'", symbol.name),
+            symbol.line,
+            symbol.col,
+        )
+    }
+
+    fn found(symbol: &Symbol, result: LookupResult) -> Result<Value, RuntimeError> {
+        let undefined = || {
+            RuntimeError::new(
+                format!("Use undefined variable '{}'", symbol.name),
+                symbol.line,
+                symbol.col,
+            )
+        };
+        match result {
+            LookupResult::Ok(val) => Ok(val),
+            LookupResult::DeclaredNotDefined(source_loc) => Err(undefined().with_note(format!(
+                "{} was declared at line {}, column {} but not defined.",
+                symbol.name, source_loc.line, source_loc.col
+            ))),
+            LookupResult::NotDeclared => Err(undefined()),
+        }
+    }
+}
+
+/// An in-memory sink for `Interpreter::output` or `error_output` that can
+/// still be read after it has been handed to the interpreter.
+#[derive(Clone, Default)]
+pub struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl SharedBuffer {
+    pub fn contents(&self) -> String {
+        String::from_utf8_lossy(&self.0.borrow()).into_owned()
+    }
+}
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// How often each kind of statement and expression was evaluated.
+#[derive(Debug, Default)]
+pub struct Profile {
+    counts: HashMap<&'static str, u64>,
+}
+
+impl Profile {
+    pub fn record(&mut self, kind: &'static str) {
+        *self.counts.entry(kind).or_insert(0) += 1;
+    }
+
+    pub fn count(&self, kind: &str) -> u64 {
+        self.counts.get(kind).copied().unwrap_or(0)
+    }
+}
+
+/// One `Kind: count` line per node kind, most evaluated first.
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut counts: Vec<_> = self.counts.iter().collect();
+        counts.sort_by(|(k1, n1), (k2, n2)| n2.cmp(n1).then(k1.cmp(k2)));
+        for (kind, count) in counts {
+            writeln!(f, "{}: {}", kind, count)?;
+        }
+        Ok(())
+    }
 }
 
 pub struct Interpreter {
-    pub env: Environment,
+    pub env: Rc<RefCell<Environment>>,
+    /// Top-level bindings of the script. Natives live one level further
+    /// out, so they don't show up here.
+    pub globals: Rc<RefCell<Environment>>,
+    /// Run `return f(...)` to a Lox function by reusing the current call
+    /// instead of recursing, so tail-recursive loops run in constant stack.
+    pub tail_call_optimization: bool,
+    /// Print number literals as written (`print 1.10;` shows `1.10`)
+    /// rather than formatting the parsed value.
+    pub preserve_literal_formatting: bool,
+    /// Print very large and very small numbers in exponential form;
+    /// `None` always prints every digit.
+    pub scientific_notation: Option<ScientificThresholds>,
+    /// How many significant digits non-integer numbers are printed with;
+    /// `None` prints the shortest form that reads back as the same number.
+    /// `Some(0)` is taken as 1; `lox` rejects it up front.
+    pub float_precision: Option<usize>,
+    /// Treat `nil` as 0 in arithmetic and as `""` when added to a string,
+    /// instead of raising an error.
+    pub loose_nil_arithmetic: bool,
+    /// Where `read_line` reads from; stdin unless an embedder swaps it out.
+    pub input: Box<dyn BufRead>,
+    /// Where `print` writes to; stdout by default.
+    pub output: Box<dyn Write>,
+    /// Where `eprint` and `debug` write to; stderr by default.
+    pub error_output: Box<dyn Write>,
+    /// Counts evaluated nodes when set; `None` skips the bookkeeping.
+    pub profile: Option<Profile>,
+    /// Writes each statement to `error_output` before running it, and the
+    /// value of each expression statement after.
+    pub trace: bool,
+    /// Attaches the variables in scope to a runtime error, innermost scope
+    /// first, as `RuntimeError::bindings`.
+    pub dump_env_on_error: bool,
+    /// Time source for `clock`, `clock_millis` and `sleep`; swap in a
+    /// `VirtualClock` to keep `sleep` from blocking.
+    pub clock: Box<dyn Clock>,
+    /// Where `random` draws from; reseeded by `srand`.
+    pub rng: Rng,
+    /// Calls nested deeper than this are reported as a stack overflow
+    /// instead of exhausting the native stack. Raising it needs a thread
+    /// with a bigger stack to match.
+    pub max_call_depth: usize,
+    retval: Option<Value>,
+    tail_call: Option<(Rc<LoxFunction>, Vec<Value>, SourceLocation)>,
+    /// Set by `break` / `continue` while unwinding to their loop.
+    loop_control: Option<LoopControl>,
+    /// An error raised inside a callback, on its way out through the native
+    /// that made the call.
+    callback_error: Option<RuntimeError>,
+    call_depth: usize,
+}
+
+enum LoopControl {
+    Break(Option<String>),
+    Continue(Option<String>),
 }
 
 impl Default for Interpreter {
     fn default() -> Self {
+        let mut builtins = Environment::new();
+        natives::define_globals(&mut builtins);
+        let globals = Rc::new(RefCell::new(Environment::with_enclosing(Rc::new(
+            RefCell::new(builtins),
+        ))));
         Interpreter {
-            env: Environment::new(),
+            env: globals.clone(),
+            globals,
+            tail_call_optimization: false,
+            preserve_literal_formatting: false,
+            scientific_notation: None,
+            float_precision: None,
+            loose_nil_arithmetic: false,
+            input: Box::new(BufReader::new(io::stdin())),
+            output: Box::new(io::stdout()),
+            error_output: Box::new(io::stderr()),
+            profile: None,
+            trace: false,
+            dump_env_on_error: false,
+            clock: Box::new(SystemClock::default()),
+            rng: Rng::default(),
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+            retval: None,
+            tail_call: None,
+            loop_control: None,
+            callback_error: None,
+            call_depth: 0,
         }
     }
 }
 
 impl Interpreter {
-    pub fn interpret(&mut self, stmts: &[Stmt]) -> Result<(), String> {
+    /// Makes a Rust function callable from scripts under `name`, next to
+    /// the built-in natives. Scripts can shadow it like any other builtin.
+    pub fn register_native<F>(&mut self, name: &str, arity: usize, f: F)
+    where
+        F: Fn(&[Value]) -> Result<Value, String> + 'static,
+    {
+        let native = NativeFunction {
+            name: name.to_string(),
+            arity: Arity::Exactly(arity),
+            callable: Rc::new(move |_, args| f(args)),
+        };
+        let symbol = Symbol {
+            name: name.to_string(),
+            line: 0,
+            col: -1,
+        };
+        let globals = self.globals.borrow();
+        let builtins = globals
+            .enclosing
+            .as_ref()
+            .expect("globals are enclosed by the builtins");
+        builtins
+            .borrow_mut()
+            .define(symbol, Some(Value::NativeFunction(native)));
+    }
+
+    /// Every name visible at the top level: the script's globals and the
+    /// natives, registered ones included.
+    pub(crate) fn global_names(&self) -> Vec<String> {
+        let globals = self.globals.borrow();
+        let mut names = globals.names().to_vec();
+        if let Some(builtins) = &globals.enclosing {
+            names.extend(builtins.borrow().names().iter().cloned());
+        }
+        names
+    }
+
+    /// Stops scripts from reaching the host through natives; see
+    /// `natives::sandbox`.
+    pub fn enable_sandbox(&mut self) {
+        let globals = self.globals.borrow();
+        let builtins = globals
+            .enclosing
+            .as_ref()
+            .expect("globals are enclosed by the builtins");
+        natives::sandbox(&mut builtins.borrow_mut());
+    }
+
+    pub fn interpret(&mut self, stmts: &[Stmt]) -> Result<(), RuntimeError> {
+        self.hoist_functions(stmts);
         for stmt in stmts {
             self.evaluate(stmt)?;
         }
         Ok(())
     }
-    pub fn evaluate(&mut self, stmt: &Stmt) -> Result<(), String> {
-        match stmt {
-            Stmt::Expr(expr) => match self.evaluate_expr(expr) {
-                Ok(_) => Ok(()),
-                Err(err) => Err(err),
-            },
-            Stmt::Print(e) => match self.evaluate_expr(e) {
-                Ok(val) => {
-                    println!("{}", self.format_val(&val));
-                    // todo: collect output.
-                    Ok(())
+
+    /// Runs `source` in the global scope, as `eval` does, and returns the
+    /// value of its last statement if that is an expression, or nil. A
+    /// missing final `;` is tolerated, so `eval("1 + 2")` is 3. Nested
+    /// `eval`s count against the call depth limit.
+    pub fn eval_source(&mut self, source: &str) -> Result<Value, RuntimeError> {
+        let program = crate::parse_line(source, false)
+            .map_err(|errors| RuntimeError::new(format!("eval: {}", errors[0].message), 0, -1))?;
+        if self.call_depth >= self.max_call_depth {
+            return Err(RuntimeError::new("Stack overflow in eval", 0, -1));
+        }
+        let previous = std::mem::replace(&mut self.env, self.globals.clone());
+        self.call_depth += 1;
+        let result = self.eval_program(&program);
+        self.call_depth -= 1;
+        self.env = previous;
+        result
+    }
+
+    fn eval_program(&mut self, program: &[Stmt]) -> Result<Value, RuntimeError> {
+        self.hoist_functions(program);
+        let mut value = Value::Nil;
+        for stmt in program {
+            value = match stmt {
+                Stmt::Expr(expr) => {
+                    if let Some(profile) = &mut self.profile {
+                        profile.record(stmt.kind());
+                    }
+                    self.evaluate_expr(expr)?
                 }
-                Err(err) => Err(err),
-            },
-            Stmt::VarDecl(symbol, initilizer) => {
-                let val = match initilizer {
-                    Some(expr) => Some(self.evaluate_expr(expr)?),
-                    None => None,
-                };
-                self.env.define(symbol.clone(), val);
-                Ok(())
+                _ => {
+                    self.evaluate(stmt)?;
+                    Value::Nil
+                }
+            };
+            if self.unwinding() {
+                break;
             }
         }
+        // The depth was raised for the limit's sake, which also lets a
+        // `return` through; it has no function to return from.
+        if self.retval.take().is_some() || self.tail_call.take().is_some() {
+            return Err(RuntimeError::new(
+                "Can't return from code run by eval",
+                0,
+                -1,
+            ));
+        }
+        Ok(value)
     }
 
-    pub fn evaluate_expr(&mut self, expr: &Expr) -> Result<Value, String> {
-        match expr {
-            Expr::Literal(literal) => Ok(self.visit_literal(literal)),
-            Expr::Unary(op, e) => self.visit_unary(*op, e),
-            Expr::Binary(lhs, op, rhs) => self.visit_binary(lhs, op.clone(), rhs),
-            Expr::Grouping(e) => self.evaluate_expr(e),
-            _ => Err("E".to_string()),
+    fn execute_block(&mut self, stmts: &[Stmt], env: Environment) -> Result<(), RuntimeError> {
+        let previous = std::mem::replace(&mut self.env, Rc::new(RefCell::new(env)));
+        self.hoist_functions(stmts);
+        let mut result = Ok(());
+        for stmt in stmts {
+            result = self.evaluate(stmt);
+            if result.is_err() || self.unwinding() {
+                break;
+            }
         }
+        self.env = previous;
+        result
     }
-
-    fn visit_literal(&mut self, expr: &Literal) -> Value {
-        match expr {
-            Literal::String(s) => Value::String(s.clone()),
-            Literal::Number(n) => Value::Number(*n),
-            Literal::True => Value::Boolean(true),
-            Literal::False => Value::Boolean(false),
-            Literal::Nil => Value::Nil,
+    /// Declares the functions among `stmts` in the current scope before
+    /// any of them runs, so a function can be called above its
+    /// declaration, and two can call each other in either order. Reaching
+    /// the declaration itself binds the function again, to the same thing.
+    pub(crate) fn hoist_functions(&mut self, stmts: &[Stmt]) {
+        for stmt in stmts {
+            if let Stmt::FunDecl(decl) = stmt {
+                self.declare_function(decl);
+            }
         }
     }
 
-    fn visit_unary(&mut self, op: UnaryOp, expr: &Expr) -> Result<Value, String> {
+    fn declare_function(&mut self, decl: &FunDecl) {
+        let fun = LoxFunction {
+            decl: decl.clone(),
+            closure: self.env.clone(),
+        };
+        self.env
+            .borrow_mut()
+            .define_immutable(decl.name.clone(), Value::LoxFunction(Rc::new(fun)));
+    }
+
+    /// Whether a `return`, tail call, `break` or `continue` is on its way
+    /// out and the remaining statements must be skipped.
+    fn unwinding(&self) -> bool {
+        self.retval.is_some() || self.tail_call.is_some() || self.loop_control.is_some()
+    }
+
+    /// Runs the body of the loop labeled `label` once and says whether the
+    /// loop should go on. A `break` or `continue` aimed at an outer loop is
+    /// left in place for that loop to handle.
+    fn loop_iteration(
+        &mut self,
+        label: Option<&Symbol>,
+        body: &Stmt,
+    ) -> Result<bool, RuntimeError> {
+        self.evaluate(body)?;
+        if self.retval.is_some() || self.tail_call.is_some() {
+            return Ok(false);
+        }
+        let targets_this = |target: &Option<String>| match target {
+            None => true,
+            Some(target) => label.is_some_and(|label| &label.name == target),
+        };
+        match self.loop_control.take() {
+            None => Ok(true),
+            Some(LoopControl::Continue(target)) if targets_this(&target) => Ok(true),
+            Some(LoopControl::Break(target)) if targets_this(&target) => Ok(false),
+            outer => {
+                self.loop_control = outer;
+                Ok(false)
+            }
+        }
+    }
+
+    pub fn evaluate(&mut self, stmt: &Stmt) -> Result<(), RuntimeError> {
+        if let Some(profile) = &mut self.profile {
+            profile.record(stmt.kind());
+        }
+        let result = if self.trace {
+            self.trace_stmt(stmt)
+        } else {
+            stmt.accept(self)
+        };
+        self.with_env_dump(result)
+    }
+
+    /// Runs the expression statement `stmt`, wrapping `expr`, the way
+    /// `evaluate` would, but hands back the value instead of dropping it.
+    pub(crate) fn evaluate_expr_stmt(
+        &mut self,
+        stmt: &Stmt,
+        expr: &Expr,
+    ) -> Result<Value, RuntimeError> {
+        if let Some(profile) = &mut self.profile {
+            profile.record(stmt.kind());
+        }
+        let result = if self.trace {
+            self.trace_expr_stmt(stmt, expr)
+        } else {
+            self.evaluate_expr(expr)
+        };
+        self.with_env_dump(result)
+    }
+
+    fn with_env_dump<T>(&self, result: Result<T, RuntimeError>) -> Result<T, RuntimeError> {
+        match result {
+            // The innermost statement sees the error first, while its own
+            // scope is still the current one.
+            Err(mut err) if self.dump_env_on_error && err.bindings.is_none() => {
+                err.bindings = Some(self.dump_env());
+                Err(err)
+            }
+            result => result,
+        }
+    }
+
+    /// Lists every binding from the current scope out to the globals, with
+    /// a header per scope. Natives are left out.
+    fn dump_env(&self) -> String {
+        let mut dump = String::new();
+        let mut env = self.env.clone();
+        let mut depth = 0;
+        loop {
+            let at_globals = Rc::ptr_eq(&env, &self.globals);
+            if at_globals {
+                dump.push_str("globals:\n");
+            } else {
+                depth += 1;
+                dump.push_str(&format!("scope {}:\n", depth));
+            }
+            let enclosing = {
+                let scope = env.borrow();
+                for name in scope.names() {
+                    let shown = match &scope.values[name].value {
+                        Some(Value::String(s)) => format!("\"{}\"", s),
+                        Some(val) => self.format_val(val),
+                        None => "<declared but not defined>".to_string(),
+                    };
+                    dump.push_str(&format!("  {} = {}\n", name, shown));
+                }
+                scope.enclosing.clone()
+            };
+            match enclosing {
+                Some(next) if !at_globals => env = next,
+                _ => break,
+            }
+        }
+        dump
+    }
+
+    fn trace_stmt(&mut self, stmt: &Stmt) -> Result<(), RuntimeError> {
+        if let Stmt::Expr(expr) = stmt {
+            return self.trace_expr_stmt(stmt, expr).map(|_| ());
+        }
+        let text = format!("[trace] {}", ast_printer::print_stmt(stmt));
+        Self::write_line(&mut self.error_output, &text)?;
+        stmt.accept(self)
+    }
+
+    fn trace_expr_stmt(&mut self, stmt: &Stmt, expr: &Expr) -> Result<Value, RuntimeError> {
+        let text = format!("[trace] {}", ast_printer::print_stmt(stmt));
+        Self::write_line(&mut self.error_output, &text)?;
         let val = self.evaluate_expr(expr)?;
+        let text = format!("[trace] => {}", self.format_val(&val));
+        Self::write_line(&mut self.error_output, &text)?;
+        Ok(val)
+    }
 
-        match (op.op_type, &val) {
-            (UnaryOpType::Minus, Value::Number(n)) => Ok(Value::Number(-n)),
-            (UnaryOpType::Bang, Value::Number(_)) => Ok(Value::Boolean(!Self::is_truthy(&val))),
-            (_, Value::String(_)) => Err(format!(
-                "Invalid use of unary operator '{:?}' on a String type at line {}, column {}.",
-                op.op_type, op.line, op.col
+    pub fn evaluate_expr(&mut self, expr: &Expr) -> Result<Value, RuntimeError> {
+        if let Some(profile) = &mut self.profile {
+            profile.record(expr.kind());
+        }
+        expr.accept(self)
+    }
+
+    /// Arguments are evaluated left to right, after the callee, and the
+    /// first one to fail stops the rest from being evaluated at all. Their
+    /// side effects rely on this order, so it is spelled out as a loop.
+    fn evaluate_args(&mut self, args: &[Expr]) -> Result<Vec<Value>, RuntimeError> {
+        let mut values = Vec::with_capacity(args.len());
+        for arg in args {
+            values.push(self.evaluate_expr(arg)?);
+        }
+        Ok(values)
+    }
+
+    /// Calls `callee` on behalf of a native. The native's own call site is
+    /// what gets reported, so this one has no location.
+    pub(crate) fn call_value(
+        &mut self,
+        callee: Value,
+        args: Vec<Value>,
+    ) -> Result<Value, RuntimeError> {
+        let loc = SourceLocation { line: 0, col: -1 };
+        self.call(callee, &loc, args)
+    }
+
+    /// Like `call_value`, for natives that pass a callback's error on. An
+    /// error raised in Lox code keeps its own location; one without a
+    /// location, such as a wrong arity, is reported at the native's call.
+    pub(crate) fn call_back(&mut self, callee: Value, args: Vec<Value>) -> Result<Value, String> {
+        self.call_value(callee, args).map_err(|err| {
+            let message = err.message.clone();
+            if err.line > 0 {
+                self.callback_error = Some(err);
+            }
+            message
+        })
+    }
+
+    /// Every call goes through here, so callability and arity are checked
+    /// in one place for Lox functions and natives alike.
+    fn call(
+        &mut self,
+        callee: Value,
+        loc: &SourceLocation,
+        args: Vec<Value>,
+    ) -> Result<Value, RuntimeError> {
+        let (name, arity) = match &callee {
+            Value::LoxFunction(fun) => (&fun.decl.name.name, fun.arity()),
+            Value::NativeFunction(native) | Value::BoundMethod(_, native) => {
+                (&native.name, native.arity)
+            }
+            _ => {
+                return Err(RuntimeError::new(
+                    format!(
+                        "Can only call functions and classes, got {}",
+                        instance_of(&callee)
+                    ),
+                    loc.line,
+                    loc.col,
+                ))
+            }
+        };
+        if !arity.accepts(args.len()) {
+            return Err(Self::arity_error(name, arity, args.len(), loc));
+        }
+        match callee {
+            Value::LoxFunction(fun) => self.call_function(fun, args, loc.clone()),
+            Value::NativeFunction(native) => self.call_native(&native, args, loc),
+            Value::BoundMethod(receiver, native) => {
+                let mut full_args = vec![*receiver];
+                full_args.extend(args);
+                self.call_native(&native, full_args, loc)
+            }
+            _ => unreachable!("callability was checked above"),
+        }
+    }
+
+    fn call_native(
+        &mut self,
+        native: &NativeFunction,
+        args: Vec<Value>,
+        loc: &SourceLocation,
+    ) -> Result<Value, RuntimeError> {
+        (native.callable)(self, &args).map_err(|err| {
+            self.callback_error
+                .take()
+                .unwrap_or_else(|| RuntimeError::new(err, loc.line, loc.col))
+        })
+    }
+
+    fn arity_error(name: &str, expected: Arity, got: usize, loc: &SourceLocation) -> RuntimeError {
+        RuntimeError::new(
+            format!("Function '{}' expected {} but got {}", name, expected, got),
+            loc.line,
+            loc.col,
+        )
+    }
+
+    /// Turns a Lox index into a position in a list of `len` elements.
+    /// Negative indices count from the end, so `-1` is the last element.
+    fn list_index(index: &Value, len: usize, loc: &SourceLocation) -> Result<usize, RuntimeError> {
+        let error = |message: String| RuntimeError::new(message, loc.line, loc.col);
+        let out_of_range = || {
+            error(format!(
+                "List index {} out of range for length {}",
+                index, len
+            ))
+        };
+        let position = match index {
+            Value::Number(i) if *i < 0.0 && i.fract() == 0.0 => {
+                let from_end = Value::Number(-i).as_index().map_err(|_| out_of_range())?;
+                len.checked_sub(from_end).ok_or_else(out_of_range)?
+            }
+            _ => index
+                .as_index()
+                .map_err(|message| error(format!("List index: {}", message)))?,
+        };
+        if position >= len {
+            return Err(out_of_range());
+        }
+        Ok(position)
+    }
+
+    fn property_error(object: &Value, name: &Symbol) -> RuntimeError {
+        RuntimeError::new(
+            format!("{} has no property '{}'", instance_of(object), name.name),
+            name.line,
+            name.col,
+        )
+    }
+
+    /// For expressions the tree can hold but the interpreter can't run yet.
+    fn not_implemented(kind: &str, line: usize, col: i64) -> RuntimeError {
+        RuntimeError::new(format!("not yet implemented: {}", kind), line, col)
+    }
+
+    fn index_error(object: &Value, loc: &SourceLocation) -> RuntimeError {
+        let target = match instance_of(object) {
+            LoxType::Nil => "nil".to_string(),
+            t => format!("a {}", t),
+        };
+        RuntimeError::new(format!("Cannot index {}", target), loc.line, loc.col)
+    }
+
+    fn call_function(
+        &mut self,
+        fun: Rc<LoxFunction>,
+        args: Vec<Value>,
+        loc: SourceLocation,
+    ) -> Result<Value, RuntimeError> {
+        if self.call_depth >= self.max_call_depth {
+            return Err(RuntimeError::new(
+                format!("Stack overflow calling '{}'", fun.decl.name.name),
+                loc.line,
+                loc.col,
+            ));
+        }
+        self.call_depth += 1;
+        let (mut fun, mut args, mut loc) = (fun, args, loc);
+        let result = loop {
+            // Tail calls don't go back through `call`, so check them here.
+            if !fun.arity().accepts(args.len()) {
+                break Err(Self::arity_error(
+                    &fun.decl.name.name,
+                    fun.arity(),
+                    args.len(),
+                    &loc,
+                ));
+            }
+            let mut env = Environment::with_enclosing(fun.closure.clone());
+            let mut given = args.into_iter();
+            for (param, arg) in fun.decl.params.iter().zip(given.by_ref()) {
+                env.define(param.clone(), Some(arg));
+            }
+            if let Some(rest) = &fun.decl.rest {
+                let rest_args = Value::List(Rc::new(RefCell::new(given.collect())));
+                env.define(rest.clone(), Some(rest_args));
+            }
+            if let Err(err) = self.execute_block(&fun.decl.body, env) {
+                break Err(err);
+            }
+            // A tail call replaces the finished frame instead of nesting a new one.
+            match self.tail_call.take() {
+                Some((next, next_args, next_loc)) => {
+                    (fun, args, loc) = (next, next_args, next_loc);
+                }
+                None => break Ok(self.retval.take().unwrap_or(Value::Nil)),
+            }
+        };
+        self.call_depth -= 1;
+        self.retval = None;
+        result
+    }
+
+    /// `<<` / `>>` on numbers that must be integers; the shift count must
+    /// also be less than 64.
+    fn shift(value: f64, op: BinaryOp, count: f64) -> Result<Value, RuntimeError> {
+        let error = |message: String| RuntimeError::new(message, op.line, op.col);
+        // 2^63 itself doesn't fit in an i64, hence the strict bound.
+        if value.fract() != 0.0 || value.abs() >= 9_223_372_036_854_775_808.0 {
+            return Err(error(format!("Can only shift integers, got {}", value)));
+        }
+        let count = Value::Number(count)
+            .as_index()
+            .map_err(|message| error(format!("Shift count: {}", message)))?;
+        if count >= 64 {
+            return Err(error(format!(
+                "Shift count must be less than 64, got {}",
+                count
+            )));
+        }
+        let (value, count) = (value as i64, count as u32);
+        let shifted = match op.op_type {
+            expr::BinaryOpType::ShiftLeft => value << count,
+            _ => value >> count,
+        };
+        Ok(Value::Number(shifted as f64))
+    }
+
+    /// Under `loose_nil_arithmetic`, a nil operand stands in for `""` when
+    /// added to a string and for 0 otherwise. `==` and `!=` are left alone,
+    /// so `nil == 0` is still false.
+    fn loosen_nil(left: Value, op: BinaryOp, right: Value) -> (Value, Value) {
+        use expr::BinaryOpType::{BangEqual, EqualEqual, Plus};
+        if matches!(op.op_type, EqualEqual | BangEqual) {
+            return (left, right);
+        }
+        let stand_in = |other: &Value| match other {
+            Value::String(_) if matches!(op.op_type, Plus) => Value::String(String::new()),
+            _ => Value::Number(0.0),
+        };
+        match (left, right) {
+            (Value::Nil, Value::Nil) => (Value::Number(0.0), Value::Number(0.0)),
+            (Value::Nil, right) => (stand_in(&right), right),
+            (left, Value::Nil) => {
+                let right = stand_in(&left);
+                (left, right)
+            }
+            operands => operands,
+        }
+    }
+
+    /// The values a `for`/`in` goes through. A list is copied first, so
+    /// changing it inside the loop doesn't change what is visited; a range
+    /// makes its numbers as they are needed.
+    fn iteration_items(
+        iterable: &Value,
+        loc: &SourceLocation,
+    ) -> Result<Box<dyn Iterator<Item = Value>>, RuntimeError> {
+        match iterable {
+            Value::List(elements) => Ok(Box::new(elements.borrow().clone().into_iter())),
+            Value::Range(range) => Ok(Box::new(range.iter().map(Value::Number))),
+            val => Err(RuntimeError::new(
+                format!(
+                    "Can only iterate over a list or a range, got {}",
+                    instance_of(val)
+                ),
+                loc.line,
+                loc.col,
+            )),
+        }
+    }
+
+    /// The elements of `value` for destructuring into `count` names. Only
+    /// a list with exactly that many elements fits.
+    fn destructure(
+        value: &Value,
+        count: usize,
+        loc: &SourceLocation,
+    ) -> Result<Vec<Value>, RuntimeError> {
+        match value {
+            Value::List(elements) if elements.borrow().len() == count => {
+                Ok(elements.borrow().clone())
+            }
+            Value::List(elements) => Err(RuntimeError::new(
+                format!(
+                    "Can't destructure a list of {} element(s) into {} name(s)",
+                    elements.borrow().len(),
+                    count
+                ),
+                loc.line,
+                loc.col,
             )),
-            (_, Value::Boolean(_)) => Err(format!(
-                "Invalid use of unary operator '{:?}' on a Boolean type at line {}, column {}.",
-                op.op_type, op.line, op.col
+            val => Err(RuntimeError::new(
+                format!("Can only destructure a list, got {}", instance_of(val)),
+                loc.line,
+                loc.col,
             )),
-            (_, Value::Nil) => Err(format!(
-                "Invalid use of unary operator {:?} on a Nil type at line {}, column {}.",
-                op.op_type, op.line, op.col
+        }
+    }
+
+    /// Points at the parentheses of a grouped operand, which the operator's
+    /// location alone doesn't show.
+    fn grouping_note(side: &str, expr: &Expr, val: &Value) -> Option<String> {
+        match expr {
+            Expr::Grouping(loc, _) => Some(format!(
+                "The {} operand is the {} in parentheses at line {}, column {}.",
+                side,
+                instance_of(val),
+                loc.line,
+                loc.col
             )),
+            _ => None,
         }
     }
 
-    fn visit_binary(&mut self, lhs: &Expr, op: BinaryOp, rhs: &Expr) -> Result<Value, String> {
+    fn write_line(out: &mut Box<dyn Write>, text: &str) -> Result<(), RuntimeError> {
+        writeln!(out, "{}", text)
+            .map_err(|err| RuntimeError::new(format!("Failed to write output: {}", err), 0, -1))
+    }
+
+    pub fn format_val(&self, val: &Value) -> String {
+        Formatted {
+            value: val,
+            numbers: NumberFormat {
+                scientific: self.scientific_notation,
+                float_precision: self.float_precision,
+            },
+        }
+        .to_string()
+    }
+
+    pub(crate) fn is_truthy(val: &Value) -> bool {
+        match val {
+            Value::Nil => false,
+            Value::Boolean(b) => *b,
+            _ => true,
+        }
+    }
+
+    /// `==` in Lox. Lists and maps compare by contents: lists element by
+    /// element, maps by having the same keys mapped to equal values.
+    pub fn equals(lhs: &Value, rhs: &Value) -> bool {
+        Self::equals_at(lhs, rhs, 0)
+    }
+
+    fn equals_at(lhs: &Value, rhs: &Value, depth: usize) -> bool {
+        match (lhs, rhs) {
+            (Value::Boolean(b1), Value::Boolean(b2)) => b1 == b2,
+            (Value::String(s1), Value::String(s2)) => s1 == s2,
+            (Value::Number(n1), Value::Number(n2)) => {
+                if n1 == n2 {
+                    // There is a single number type, so `2 == 2.0` is just
+                    // f64 equality. This also covers `inf == inf`, which
+                    // the subtraction below can't.
+                    true
+                } else if n1.is_nan() || n2.is_nan() || n1.fract() == 0.0 || n2.fract() == 0.0 {
+                    // Integers compare exactly, so `0 == 1e-20` is false.
+                    false
+                } else {
+                    (n1 - n2).abs() < f64::EPSILON
+                }
+            }
+            // Nil != Nil
+            (Value::Nil, Value::Nil) => true,
+            (Value::LoxFunction(f1), Value::LoxFunction(f2)) => Rc::ptr_eq(f1, f2),
+            (Value::NativeFunction(f1), Value::NativeFunction(f2)) => f1.name == f2.name,
+            // Past the depth limit, nested containers are taken to differ;
+            // that is what ends the walk through a list holding itself.
+            (Value::List(l1), Value::List(l2)) => {
+                Rc::ptr_eq(l1, l2)
+                    || depth < MAX_EQUALITY_DEPTH && {
+                        let (l1, l2) = (l1.borrow(), l2.borrow());
+                        l1.len() == l2.len()
+                            && l1
+                                .iter()
+                                .zip(l2.iter())
+                                .all(|(a, b)| Self::equals_at(a, b, depth + 1))
+                    }
+            }
+            (Value::Bytes(b1), Value::Bytes(b2)) => b1 == b2,
+            (Value::Range(r1), Value::Range(r2)) => r1 == r2,
+            (Value::Map(m1), Value::Map(m2)) => {
+                Rc::ptr_eq(m1, m2)
+                    || depth < MAX_EQUALITY_DEPTH && {
+                        let (m1, m2) = (m1.borrow(), m2.borrow());
+                        m1.len() == m2.len()
+                            && m1.entries.iter().all(|(key, v1)| {
+                                m2.get(key)
+                                    .is_some_and(|v2| Self::equals_at(v1, v2, depth + 1))
+                            })
+                    }
+            }
+            _ => false,
+        }
+    }
+}
+
+impl ExprVisitor for Interpreter {
+    type Output = Result<Value, RuntimeError>;
+
+    /// Yields the assigned value, so `print (x = 5);` prints 5 and
+    /// `a = b = 0;` sets both.
+    fn visit_assign(&mut self, name: &Symbol, value: &Expr) -> Self::Output {
+        let val = self.evaluate_expr(value)?;
+        self.env.borrow_mut().assign(name, &val)?;
+        Ok(val)
+    }
+
+    /// Yields the whole list, like a plain assignment yields its value.
+    fn visit_assign_destructure(
+        &mut self,
+        loc: &SourceLocation,
+        names: &[Symbol],
+        value: &Expr,
+    ) -> Self::Output {
+        let list = self.evaluate_expr(value)?;
+        for (name, element) in names
+            .iter()
+            .zip(Self::destructure(&list, names.len(), loc)?)
+        {
+            self.env.borrow_mut().assign(name, &element)?;
+        }
+        Ok(list)
+    }
+
+    fn visit_unary(&mut self, op: &UnaryOp, operand: &Expr) -> Self::Output {
+        let op = *op;
+        let val = match self.evaluate_expr(operand)? {
+            Value::Nil if self.loose_nil_arithmetic && matches!(op.op_type, UnaryOpType::Minus) => {
+                Value::Number(0.0)
+            }
+            val => val,
+        };
+
+        let result = match (op.op_type, &val) {
+            (UnaryOpType::Minus, Value::Number(n)) => Ok(Value::Number(-n)),
+            (UnaryOpType::Bang, _) => Ok(Value::Boolean(!Self::is_truthy(&val))),
+            (_, Value::String(_)) => Err(RuntimeError::new(
+                format!(
+                    "Invalid use of unary operator '{:?}' on a String type",
+                    op.op_type
+                ),
+                op.line,
+                op.col,
+            )),
+            (_, Value::Boolean(_)) => Err(RuntimeError::new(
+                format!(
+                    "Invalid use of unary operator '{:?}' on a Boolean type",
+                    op.op_type
+                ),
+                op.line,
+                op.col,
+            )),
+            (_, Value::Nil) => Err(RuntimeError::new(
+                format!(
+                    "Invalid use of unary operator {:?} on a Nil type",
+                    op.op_type
+                ),
+                op.line,
+                op.col,
+            )),
+            (_, Value::LoxFunction(_) | Value::NativeFunction(_) | Value::BoundMethod(..)) => {
+                Err(RuntimeError::new(
+                    format!(
+                        "Invalid use of unary operator {:?} on a Function type",
+                        op.op_type
+                    ),
+                    op.line,
+                    op.col,
+                ))
+            }
+            (_, Value::List(_)) => Err(RuntimeError::new(
+                format!(
+                    "Invalid use of unary operator {:?} on a List type",
+                    op.op_type
+                ),
+                op.line,
+                op.col,
+            )),
+            (_, Value::Bytes(_)) => Err(RuntimeError::new(
+                format!(
+                    "Invalid use of unary operator {:?} on a Bytes type",
+                    op.op_type
+                ),
+                op.line,
+                op.col,
+            )),
+            (_, Value::Map(_)) => Err(RuntimeError::new(
+                format!(
+                    "Invalid use of unary operator {:?} on a Map type",
+                    op.op_type
+                ),
+                op.line,
+                op.col,
+            )),
+            (_, Value::Range(_)) => Err(RuntimeError::new(
+                format!(
+                    "Invalid use of unary operator {:?} on a Range type",
+                    op.op_type
+                ),
+                op.line,
+                op.col,
+            )),
+        };
+        // Operand errors have already returned, so only the operator's own
+        // failure gets the span of the whole expression.
+        result.map_err(|err| err.with_span(op.span))
+    }
+
+    fn visit_binary(&mut self, lhs: &Expr, op: &BinaryOp, rhs: &Expr) -> Self::Output {
+        let op = *op;
         // todo: We could have instead specified that the left operand is checked before even evaluating the right.
-        let left = self.evaluate_expr(lhs).unwrap();
-        let right = self.evaluate_expr(rhs).unwrap();
-        match (&left, op.op_type, &right) {
+        let left = self.evaluate_expr(lhs)?;
+        let right = self.evaluate_expr(rhs)?;
+        let (left, right) = if self.loose_nil_arithmetic {
+            Self::loosen_nil(left, op, right)
+        } else {
+            (left, right)
+        };
+        let result = match (&left, op.op_type, &right) {
             (Value::Number(l), expr::BinaryOpType::Greater, Value::Number(r)) => {
                 Ok(Value::Boolean(l > r))
             }
@@ -210,56 +1759,1125 @@ impl Interpreter {
                 if *rn != 0.0 {
                     Ok(Value::Number(ln / rn))
                 } else {
-                    Err(format!("ZeroDivisionError: division by zero at line {}, column {}.", op.line, op.col))
+                    Err(RuntimeError::new(
+                        "ZeroDivisionError: division by zero",
+                        op.line,
+                        op.col,
+                    ))
+                }
+            }
+            (Value::Number(ln), expr::BinaryOpType::Div, Value::Number(rn)) => {
+                if *rn != 0.0 {
+                    Ok(Value::Number((ln / rn).floor()))
+                } else {
+                    Err(RuntimeError::new(
+                        "ZeroDivisionError: integer division by zero",
+                        op.line,
+                        op.col,
+                    ))
                 }
             }
+            (
+                Value::Number(ln),
+                expr::BinaryOpType::ShiftLeft | expr::BinaryOpType::ShiftRight,
+                Value::Number(rn),
+            ) => Self::shift(*ln, op, *rn),
+            (Value::String(s), expr::BinaryOpType::Star, Value::Number(n))
+            | (Value::Number(n), expr::BinaryOpType::Star, Value::String(s)) => {
+                let count = Value::Number(*n).as_index().map_err(|message| {
+                    RuntimeError::new(
+                        format!("String repetition count: {}", message),
+                        op.line,
+                        op.col,
+                    )
+                })?;
+                Ok(Value::String(s.repeat(count)))
+            }
             (Value::String(ls), expr::BinaryOpType::Plus, Value::String(rs)) => {
-                Ok(Value::String(String::from(ls) + &rs))
+                Ok(Value::String(String::from(ls) + rs))
             }
+            // Adding anything to a string stringifies it exactly the way
+            // `print` would, so `"x" + true` is `"xtrue"`.
+            (Value::String(_), expr::BinaryOpType::Plus, _)
+            | (_, expr::BinaryOpType::Plus, Value::String(_)) => Ok(Value::String(
+                self.format_val(&left) + &self.format_val(&right),
+            )),
             (_, expr::BinaryOpType::EqualEqual, _) => {
                 Ok(Value::Boolean(Self::equals(&left, &right)))
             }
             (_, expr::BinaryOpType::BangEqual, _) => {
                 Ok(Value::Boolean(!Self::equals(&left, &right)))
             }
-            _ => Err(format!(
-                "Invalid operands for binary operator {:?} of types {:?} and {:?} at line {}, column {}.",
-                op.op_type, instance_of(&left), instance_of(&right),  op.line, op.col
-            )),
+            _ => {
+                let err = RuntimeError::new(
+                    format!(
+                        "Invalid operands for binary operator {:?} of types {:?} and {:?}",
+                        op.op_type,
+                        instance_of(&left),
+                        instance_of(&right)
+                    ),
+                    op.line,
+                    op.col,
+                );
+                let notes: Vec<_> = [("left", lhs, &left), ("right", rhs, &right)]
+                    .into_iter()
+                    .filter_map(|(side, expr, val)| Self::grouping_note(side, expr, val))
+                    .collect();
+                Err(if notes.is_empty() {
+                    err
+                } else {
+                    err.with_note(notes.join(" "))
+                })
+            }
+        };
+        // Operand errors have already returned, so only the operator's own
+        // failure gets the span of the whole expression.
+        result.map_err(|err| err.with_span(op.span))
+    }
+
+    fn visit_call(&mut self, callee: &Expr, loc: &SourceLocation, args: &[Expr]) -> Self::Output {
+        let callee = self.evaluate_expr(callee)?;
+        let args = self.evaluate_args(args)?;
+        self.call(callee, loc, args)
+    }
+
+    /// `?.` only guards its own object, so in `a?.b.c` a nil `a` still fails
+    /// at `.c`.
+    fn visit_get(&mut self, object: &Expr, name: &Symbol, nil_safe: bool) -> Self::Output {
+        let object = self.evaluate_expr(object)?;
+        if nil_safe && matches!(object, Value::Nil) {
+            return Ok(Value::Nil);
         }
+        let property = match &object {
+            Value::String(s) => natives::string_property(s, &name.name),
+            Value::Map(map) => natives::map_property(map, &name.name),
+            _ => None,
+        };
+        // Properties every value has come last, so a type can override them.
+        property
+            .or_else(|| natives::value_property(&object, &name.name))
+            .ok_or_else(|| Self::property_error(&object, name))
     }
 
-    fn format_val(&self, val: &Value) -> String {
-        match val {
-            Value::Number(n) => format!("{}", n),
-            Value::String(s) => format!("{}", s),
-            Value::Nil => "nil".to_string(),
-            Value::Boolean(b) => format!("{}", b),
+    fn visit_index(&mut self, object: &Expr, loc: &SourceLocation, index: &Expr) -> Self::Output {
+        let object = self.evaluate_expr(object)?;
+        let index = self.evaluate_expr(index)?;
+        match &object {
+            Value::List(elements) => {
+                let elements = elements.borrow();
+                let i = Self::list_index(&index, elements.len(), loc)?;
+                Ok(elements[i].clone())
+            }
+            _ => Err(Self::index_error(&object, loc)),
         }
     }
 
-    fn is_truthy(val: &Value) -> bool {
-        match val {
-            Value::Nil => false,
-            Value::Boolean(b) => *b,
-            _ => true,
+    /// Like `visit_assign`, yields the stored value.
+    fn visit_set_index(
+        &mut self,
+        object: &Expr,
+        loc: &SourceLocation,
+        index: &Expr,
+        value: &Expr,
+    ) -> Self::Output {
+        let object = self.evaluate_expr(object)?;
+        let index = self.evaluate_expr(index)?;
+        let value = self.evaluate_expr(value)?;
+        match &object {
+            Value::List(elements) => {
+                let mut elements = elements.borrow_mut();
+                let i = Self::list_index(&index, elements.len(), loc)?;
+                elements[i] = value.clone();
+                Ok(value)
+            }
+            _ => Err(Self::index_error(&object, loc)),
         }
     }
 
-    fn equals(lhs: &Value, rhs: &Value) -> bool {
-        match (lhs, rhs) {
-            (Value::Boolean(b1), Value::Boolean(b2)) => b1 == b2,
-            (Value::String(s1), Value::String(s2)) => s1 == s2,
-            (Value::Number(n1), Value::Number(n2)) => {
-                if n1.is_nan() || n2.is_nan() {
-                    false
-                } else {
-                    (n1 - n2).abs() < f64::EPSILON
+    fn visit_list(&mut self, elements: &[Expr]) -> Self::Output {
+        let elements = self.evaluate_args(elements)?;
+        Ok(Value::List(Rc::new(RefCell::new(elements))))
+    }
+
+    /// Each element gets a fresh scope holding just the loop variable, so
+    /// closures made in `element` capture their own copy.
+    fn visit_comprehension(
+        &mut self,
+        element: &Expr,
+        loc: &SourceLocation,
+        name: &Symbol,
+        iterable: &Expr,
+    ) -> Self::Output {
+        let iterable = self.evaluate_expr(iterable)?;
+        let mut collected = Vec::new();
+        for item in Self::iteration_items(&iterable, loc)? {
+            let mut scope = Environment::with_enclosing(self.env.clone());
+            scope.define(name.clone(), Some(item));
+            let previous = std::mem::replace(&mut self.env, Rc::new(RefCell::new(scope)));
+            let result = self.evaluate_expr(element);
+            self.env = previous;
+            collected.push(result?);
+        }
+        Ok(Value::List(Rc::new(RefCell::new(collected))))
+    }
+
+    fn visit_grouping(&mut self, _loc: &SourceLocation, expr: &Expr) -> Self::Output {
+        self.evaluate_expr(expr)
+    }
+
+    fn visit_literal(&mut self, literal: &Literal) -> Self::Output {
+        Ok(match literal {
+            Literal::String(s) => Value::String(s.clone()),
+            Literal::Number(n, _) => Value::Number(*n),
+            Literal::True => Value::Boolean(true),
+            Literal::False => Value::Boolean(false),
+            Literal::Nil => Value::Nil,
+        })
+    }
+
+    /// Yields the operand that decided the result, not a boolean. The
+    /// right operand is only evaluated when it is needed, so an error in
+    /// it is reported from its own location or not at all.
+    fn visit_logical(&mut self, lhs: &Expr, op: &LogicalOp, rhs: &Expr) -> Self::Output {
+        let left = self.evaluate_expr(lhs)?;
+        let short_circuit = match op {
+            LogicalOp::Or => Self::is_truthy(&left),
+            LogicalOp::And => !Self::is_truthy(&left),
+        };
+        if short_circuit {
+            Ok(left)
+        } else {
+            self.evaluate_expr(rhs)
+        }
+    }
+
+    fn visit_set(&mut self, _object: &Expr, name: &Symbol, _value: &Expr) -> Self::Output {
+        Err(Self::not_implemented("Set", name.line, name.col))
+    }
+
+    fn visit_super(&mut self, loc: &SourceLocation, _method: &Symbol) -> Self::Output {
+        Err(Self::not_implemented("Super", loc.line, loc.col))
+    }
+
+    fn visit_this(&mut self, loc: &SourceLocation) -> Self::Output {
+        Err(Self::not_implemented("This", loc.line, loc.col))
+    }
+
+    fn visit_variable(&mut self, name: &Symbol) -> Self::Output {
+        self.env.borrow().get(name)
+    }
+}
+
+impl StmtVisitor for Interpreter {
+    type Output = Result<(), RuntimeError>;
+
+    /// The value is dropped, but the expression is always evaluated in
+    /// full, so its effects happen and its errors still stop the program.
+    fn visit_expr_stmt(&mut self, expr: &Expr) -> Self::Output {
+        self.evaluate_expr(expr)?;
+        Ok(())
+    }
+
+    fn visit_fun_decl(&mut self, decl: &FunDecl) -> Self::Output {
+        self.declare_function(decl);
+        Ok(())
+    }
+
+    fn visit_if(
+        &mut self,
+        condition: &Expr,
+        then_branch: &Stmt,
+        else_branch: Option<&Stmt>,
+    ) -> Self::Output {
+        if Self::is_truthy(&self.evaluate_expr(condition)?) {
+            self.evaluate(then_branch)
+        } else if let Some(else_branch) = else_branch {
+            self.evaluate(else_branch)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn visit_print(&mut self, expr: &Expr) -> Self::Output {
+        if let Expr::Literal(Literal::Number(_, Some(lexeme))) = expr {
+            if self.preserve_literal_formatting {
+                let lexeme = lexeme.clone();
+                return Self::write_line(&mut self.output, &lexeme);
+            }
+        }
+        let val = self.evaluate_expr(expr)?;
+        let text = self.format_val(&val);
+        Self::write_line(&mut self.output, &text)
+    }
+
+    fn visit_eprint(&mut self, expr: &Expr) -> Self::Output {
+        let val = self.evaluate_expr(expr)?;
+        let text = self.format_val(&val);
+        Self::write_line(&mut self.error_output, &text)
+    }
+
+    fn visit_debug(&mut self, expr: &Expr) -> Self::Output {
+        let val = self.evaluate_expr(expr)?;
+        let text = format!("{}: {}", instance_of(&val), self.format_val(&val));
+        Self::write_line(&mut self.error_output, &text)
+    }
+
+    /// The message is only evaluated once the assertion has failed.
+    /// Without one, the condition is quoted as written.
+    fn visit_assert(
+        &mut self,
+        loc: &SourceLocation,
+        condition: &Expr,
+        message: Option<&Expr>,
+    ) -> Self::Output {
+        if Self::is_truthy(&self.evaluate_expr(condition)?) {
+            return Ok(());
+        }
+        let message = match message {
+            Some(message) => {
+                let val = self.evaluate_expr(message)?;
+                self.format_val(&val)
+            }
+            None => ast_printer::print_expr(condition),
+        };
+        Err(RuntimeError::new(
+            format!("Assertion failed: {}", message),
+            loc.line,
+            loc.col,
+        ))
+    }
+
+    fn visit_var_decl(&mut self, name: &Symbol, initializer: Option<&Expr>) -> Self::Output {
+        let val = match initializer {
+            Some(expr) => Some(self.evaluate_expr(expr)?),
+            None => None,
+        };
+        self.env.borrow_mut().define(name.clone(), val);
+        Ok(())
+    }
+
+    fn visit_var_destructure(
+        &mut self,
+        loc: &SourceLocation,
+        names: &[Symbol],
+        initializer: &Expr,
+    ) -> Self::Output {
+        let list = self.evaluate_expr(initializer)?;
+        for (name, element) in names
+            .iter()
+            .zip(Self::destructure(&list, names.len(), loc)?)
+        {
+            self.env.borrow_mut().define(name.clone(), Some(element));
+        }
+        Ok(())
+    }
+
+    fn visit_const_decl(&mut self, name: &Symbol, initializer: &Expr) -> Self::Output {
+        let val = self.evaluate_expr(initializer)?;
+        self.env.borrow_mut().define_immutable(name.clone(), val);
+        Ok(())
+    }
+
+    fn visit_block(&mut self, stmts: &[Stmt]) -> Self::Output {
+        let env = Environment::with_enclosing(self.env.clone());
+        self.execute_block(stmts, env)
+    }
+
+    fn visit_empty(&mut self) -> Self::Output {
+        Ok(())
+    }
+
+    fn visit_repeat(
+        &mut self,
+        loc: &SourceLocation,
+        label: Option<&Symbol>,
+        count: &Expr,
+        body: &Stmt,
+    ) -> Self::Output {
+        let count = self.evaluate_expr(count)?.as_index().map_err(|message| {
+            RuntimeError::new(format!("repeat count: {}", message), loc.line, loc.col)
+        })?;
+        for _ in 0..count {
+            if !self.loop_iteration(label, body)? {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn visit_while(
+        &mut self,
+        label: Option<&Symbol>,
+        condition: &Expr,
+        body: &Stmt,
+        increment: Option<&Expr>,
+    ) -> Self::Output {
+        while Self::is_truthy(&self.evaluate_expr(condition)?) {
+            if !self.loop_iteration(label, body)? {
+                break;
+            }
+            // `continue` lands here too, so a `for` loop still steps.
+            if let Some(increment) = increment {
+                self.evaluate_expr(increment)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Like a comprehension, every item gets its own scope for `name`.
+    fn visit_for_in(
+        &mut self,
+        loc: &SourceLocation,
+        label: Option<&Symbol>,
+        name: &Symbol,
+        iterable: &Expr,
+        body: &Stmt,
+    ) -> Self::Output {
+        let iterable = self.evaluate_expr(iterable)?;
+        for item in Self::iteration_items(&iterable, loc)? {
+            let mut scope = Environment::with_enclosing(self.env.clone());
+            scope.define(name.clone(), Some(item));
+            let previous = std::mem::replace(&mut self.env, Rc::new(RefCell::new(scope)));
+            let go_on = self.loop_iteration(label, body);
+            self.env = previous;
+            if !go_on? {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn visit_do_while(
+        &mut self,
+        label: Option<&Symbol>,
+        body: &Stmt,
+        condition: &Expr,
+    ) -> Self::Output {
+        loop {
+            if !self.loop_iteration(label, body)? {
+                break;
+            }
+            if !Self::is_truthy(&self.evaluate_expr(condition)?) {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn visit_break(&mut self, label: Option<&Symbol>) -> Self::Output {
+        self.loop_control = Some(LoopControl::Break(label.map(|l| l.name.clone())));
+        Ok(())
+    }
+
+    fn visit_continue(&mut self, label: Option<&Symbol>) -> Self::Output {
+        self.loop_control = Some(LoopControl::Continue(label.map(|l| l.name.clone())));
+        Ok(())
+    }
+
+    fn visit_return(&mut self, loc: &SourceLocation, value: Option<&Expr>) -> Self::Output {
+        if self.call_depth == 0 {
+            return Err(RuntimeError::new(
+                "Can't return from top-level code",
+                loc.line,
+                loc.col,
+            ));
+        }
+        let val = match value {
+            Some(Expr::Call(callee, loc, args)) if self.tail_call_optimization => {
+                let callee = self.evaluate_expr(callee)?;
+                let args = self.evaluate_args(args)?;
+                if let Value::LoxFunction(fun) = callee {
+                    self.tail_call = Some((fun, args, loc.clone()));
+                    return Ok(());
                 }
+                self.call(callee, loc, args)?
             }
-            // Nil != Nil
-            (Value::Nil, Value::Nil) => true,
-            _ => false,
+            Some(expr) => self.evaluate_expr(expr)?,
+            None => Value::Nil,
+        };
+        self.retval = Some(val);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{interpreter, on_big_stack, run, run_in};
+
+    const COUNT_DOWN: &str = "
+        fun count(n) { if (n == 0) return \"done\"; return count(n - 1); }
+        print count(1000000);";
+
+    #[test]
+    fn tail_calls_run_in_constant_stack() {
+        let (mut interpreter, output) = interpreter();
+        interpreter.tail_call_optimization = true;
+        run_in(&mut interpreter, COUNT_DOWN).unwrap();
+        assert_eq!(output.contents(), "done\n");
+    }
+
+    #[test]
+    fn tail_calls_overflow_without_the_optimization() {
+        let err = run(COUNT_DOWN).unwrap_err();
+        assert_eq!(err, "Stack overflow calling 'count'");
+    }
+
+    #[test]
+    fn plain_recursion_reaches_the_default_depth() {
+        let source = format!(
+            "fun f(n) {{ if (n == 0) return 0; return 1 + f(n - 1); }} print f({});",
+            DEFAULT_MAX_CALL_DEPTH - 10
+        );
+        let output = run(&source).unwrap();
+        assert_eq!(output, format!("{}\n", DEFAULT_MAX_CALL_DEPTH - 10));
+    }
+
+    #[test]
+    fn a_bigger_stack_allows_a_higher_limit() {
+        let output = on_big_stack(|| {
+            let (mut interpreter, output) = interpreter();
+            interpreter.max_call_depth = 10_000;
+            run_in(
+                &mut interpreter,
+                "fun f(n) { if (n == 0) return 0; return 1 + f(n - 1); } print f(9990);",
+            )
+            .map(|()| output.contents())
+        });
+        assert_eq!(output.unwrap(), "9990\n");
+    }
+
+    #[test]
+    fn the_call_depth_limit_is_configurable() {
+        let (mut interpreter, _) = interpreter();
+        interpreter.max_call_depth = 50;
+        let err = run_in(
+            &mut interpreter,
+            "fun f(n) { if (n == 0) return 0; return 1 + f(n - 1); } print f(100);",
+        )
+        .unwrap_err();
+        assert_eq!(err, "Stack overflow calling 'f'");
+    }
+
+    #[test]
+    fn for_in_goes_through_lists_and_ranges() {
+        let output = run("for (x in [1, 2]) print x; for (i in range(3, 5)) print i;").unwrap();
+        assert_eq!(output, "1\n2\n3\n4\n");
+        let err = run("for (c in \"ab\") print c;").unwrap_err();
+        assert_eq!(err, "Can only iterate over a list or a range, got string");
+    }
+
+    fn run_scientific(source: &str) -> String {
+        let (mut interpreter, output) = interpreter();
+        interpreter.scientific_notation = Some(ScientificThresholds::default());
+        run_in(&mut interpreter, source).unwrap();
+        output.contents()
+    }
+
+    #[test]
+    fn huge_and_tiny_numbers_print_in_scientific_form() {
+        let output = run_scientific("print 1000000000000000000000; print 0.0000001;");
+        assert_eq!(output, "1e21\n1e-7\n");
+    }
+
+    #[test]
+    fn numbers_within_the_thresholds_print_plainly() {
+        let output = run_scientific("print 123456.5; print 0.000001;");
+        assert_eq!(output, "123456.5\n0.000001\n");
+    }
+
+    fn runtime_error(source: &str) -> RuntimeError {
+        let program = crate::parse(source.to_string()).unwrap();
+        let (mut interpreter, _) = interpreter();
+        interpreter.interpret(&program).unwrap_err()
+    }
+
+    #[test]
+    fn a_typo_suggests_the_closest_name() {
+        let err = runtime_error("var print_count = 1; print print_cont;");
+        assert_eq!(err.note.as_deref(), Some("Did you mean 'print_count'?"));
+    }
+
+    #[test]
+    fn distant_and_short_names_get_no_suggestion() {
+        assert_eq!(runtime_error("var print_count = 1; print prnt;").note, None);
+        assert_eq!(runtime_error("var ab = 1; print ac;").note, None);
+    }
+
+    #[test]
+    fn float_precision_sets_significant_digits() {
+        let (mut interpreter, output) = interpreter();
+        interpreter.float_precision = Some(2);
+        run_in(
+            &mut interpreter,
+            "print 3.14159; print 0.000123456; print 42;",
+        )
+        .unwrap();
+        assert_eq!(output.contents(), "3.1\n0.00012\n42\n");
+    }
+
+    #[test]
+    fn numbers_print_in_their_shortest_form_by_default() {
+        let output = run("print 3.14159; print 0.1 + 0.2; print 42;").unwrap();
+        assert_eq!(output, "3.14159\n0.30000000000000004\n42\n");
+    }
+
+    #[test]
+    fn a_list_that_contains_itself_prints() {
+        let output = run("var a = [1]; a[0] = a; print a;").unwrap();
+        assert_eq!(output, "[[...]]\n");
+    }
+
+    #[test]
+    fn a_map_that_contains_itself_prints() {
+        let output = run("var m = new_map(); m.set(\"x\", m); print m;").unwrap();
+        assert_eq!(output, "{x: {...}}\n");
+    }
+
+    #[test]
+    fn a_list_shared_twice_is_not_a_cycle() {
+        let output = run("var b = [2]; print [b, b];").unwrap();
+        assert_eq!(output, "[[2], [2]]\n");
+    }
+
+    #[test]
+    fn negative_zero_and_infinities_print_plainly() {
+        let interpreter = Interpreter::default();
+        let format = |n: f64| interpreter.format_val(&Value::Number(n));
+        assert_eq!(format(-0.0), "0");
+        assert_eq!(format(f64::INFINITY), "inf");
+        assert_eq!(format(f64::NEG_INFINITY), "-inf");
+        assert_eq!(format(f64::NAN), "nan");
+        let output = run("
+            print 0 * -1;
+            var big = 1;
+            for (var i = 0; i < 309; i = i + 1) big = big * 10;
+            print big; print -big;")
+        .unwrap();
+        assert_eq!(output, "0\ninf\n-inf\n");
+    }
+
+    #[test]
+    fn functions_cannot_be_reassigned() {
+        let err = run("fun f() {} f = 1;").unwrap_err();
+        assert_eq!(err, "Cannot assign to immutable binding 'f'");
+    }
+
+    #[test]
+    fn constants_can_be_read_but_not_reassigned() {
+        assert_eq!(run("const x = 1; print x;").unwrap(), "1\n");
+        let err = run("const x = 1; x = 2;").unwrap_err();
+        assert_eq!(err, "Cannot assign to immutable binding 'x'");
+    }
+
+    #[test]
+    fn debug_prints_the_type_and_the_value() {
+        let (mut interpreter, output) = interpreter();
+        let errors = SharedBuffer::default();
+        interpreter.error_output = Box::new(errors.clone());
+        run_in(&mut interpreter, "debug \"hi\"; debug [1];").unwrap();
+        assert_eq!(errors.contents(), "string: hi\nlist: [1]\n");
+        assert_eq!(output.contents(), "");
+    }
+
+    #[test]
+    fn strings_have_a_length_and_methods() {
+        let output = run("print \"abc\".length; print \"abc\".upper();").unwrap();
+        assert_eq!(output, "3\nABC\n");
+        let err = run("print \"abc\".nope;").unwrap_err();
+        assert_eq!(err, "string has no property 'nope'");
+    }
+
+    #[test]
+    fn div_floors_and_double_slash_is_a_comment() {
+        assert_eq!(
+            run("print 7 div 2; print -7 div 2; // div 2\n").unwrap(),
+            "3\n-4\n"
+        );
+        let err = run("print 7 div 0;").unwrap_err();
+        assert_eq!(err, "ZeroDivisionError: integer division by zero");
+    }
+
+    #[test]
+    fn literals_keep_their_spelling_when_asked() {
+        let (mut interpreter, output) = interpreter();
+        interpreter.preserve_literal_formatting = true;
+        run_in(&mut interpreter, "print 1.10; print 1.10 + 0;").unwrap();
+        assert_eq!(output.contents(), "1.10\n1.1\n");
+        assert_eq!(run("print 1.10;").unwrap(), "1.1\n");
+    }
+
+    #[test]
+    fn repeat_runs_its_body_n_times() {
+        assert_eq!(run("repeat (3) { print \"x\"; }").unwrap(), "x\nx\nx\n");
+        let err = run("repeat (-1) { print \"x\"; }").unwrap_err();
+        assert_eq!(err, "repeat count: expected a non-negative integer, got -1");
+    }
+
+    #[test]
+    fn do_while_runs_its_body_once_on_a_false_condition() {
+        assert_eq!(
+            run("do { print \"once\"; } while (false);").unwrap(),
+            "once\n"
+        );
+    }
+
+    fn symbol(name: &str) -> Symbol {
+        Symbol {
+            name: name.to_string(),
+            line: 1,
+            col: 1,
+        }
+    }
+
+    #[test]
+    fn get_at_and_assign_at_reach_a_given_depth() {
+        let outer = Rc::new(RefCell::new(Environment::new()));
+        outer
+            .borrow_mut()
+            .define(symbol("x"), Some(Value::Number(1.0)));
+        let middle = Rc::new(RefCell::new(Environment::with_enclosing(outer.clone())));
+        middle
+            .borrow_mut()
+            .define(symbol("x"), Some(Value::Number(2.0)));
+        let mut inner = Environment::with_enclosing(middle);
+        let number = |val| match val {
+            Value::Number(n) => n,
+            val => panic!("{:?} is not a number", val),
+        };
+        assert_eq!(number(inner.get_at(2, &symbol("x")).unwrap()), 1.0);
+        assert_eq!(number(inner.get_at(1, &symbol("x")).unwrap()), 2.0);
+        inner
+            .assign_at(2, &symbol("x"), &Value::Number(3.0))
+            .unwrap();
+        assert_eq!(number(outer.borrow().get(&symbol("x")).unwrap()), 3.0);
+    }
+
+    #[test]
+    fn integer_and_float_forms_compare_equal() {
+        let output = run("
+            print 2 == 2.0; print 2.0 != 2; print 0 == -0;
+            print 0.1 + 0.2 == 0.3; print \"2\" == 2;")
+        .unwrap();
+        assert_eq!(output, "true\nfalse\ntrue\ntrue\nfalse\n");
+    }
+
+    #[test]
+    fn host_functions_can_be_registered() {
+        let (mut interpreter, output) = interpreter();
+        interpreter.register_native("double", 1, |args| match &args[0] {
+            Value::Number(n) => Ok(Value::Number(n * 2.0)),
+            _ => Err("double expects a number".to_string()),
+        });
+        let program = crate::parse("print double(21);".to_string()).unwrap();
+        interpreter.interpret(&program).unwrap();
+        assert_eq!(output.contents(), "42\n");
+    }
+
+    #[test]
+    fn labeled_break_leaves_both_loops() {
+        let output = run("
+            outer: for (var i = 0; i < 3; i = i + 1) {
+                for (var j = 0; j < 3; j = j + 1) {
+                    if (j == 1) continue outer;
+                    if (i == 2) break outer;
+                    print i * 10 + j;
+                }
+            }
+            print \"done\";")
+        .unwrap();
+        assert_eq!(output, "0\n10\ndone\n");
+    }
+
+    #[test]
+    fn calls_check_the_callee_and_the_arity() {
+        let err = run("var x = 1; x();").unwrap_err();
+        assert_eq!(err, "Can only call functions and classes, got number");
+        let err = run("fun f(a) {} f(1, 2);").unwrap_err();
+        assert_eq!(err, "Function 'f' expected 1 argument but got 2");
+    }
+
+    #[test]
+    fn eprint_writes_to_the_error_output() {
+        let (mut interpreter, output) = interpreter();
+        let errors = SharedBuffer::default();
+        interpreter.error_output = Box::new(errors.clone());
+        run_in(&mut interpreter, "print \"out\"; eprint \"err\";").unwrap();
+        assert_eq!(output.contents(), "out\n");
+        assert_eq!(errors.contents(), "err\n");
+    }
+
+    #[test]
+    fn a_grouped_operand_is_pointed_at() {
+        let err = runtime_error("print\n  (nil) + 1;");
+        assert_eq!((err.line, err.col), (2, 9));
+        assert_eq!(
+            err.note.as_deref(),
+            Some("The left operand is the nil in parentheses at line 2, column 3.")
+        );
+    }
+
+    #[test]
+    fn profile_counts_each_node_kind() {
+        let (mut interpreter, _) = interpreter();
+        interpreter.profile = Some(Profile::default());
+        run_in(&mut interpreter, "var x = 1 + 2; print x;").unwrap();
+        let profile = interpreter.profile.unwrap();
+        assert_eq!(
+            profile.to_string(),
+            "Literal: 2\nBinary: 1\nPrint: 1\nVarDecl: 1\nVariable: 1\n"
+        );
+        assert_eq!(profile.count("Literal"), 2);
+        assert_eq!(profile.count("While"), 0);
+    }
+
+    #[test]
+    fn booleans_print_and_concatenate_as_words() {
+        let output = run("print true; print false; print \"is \" + true;").unwrap();
+        assert_eq!(output, "true\nfalse\nis true\n");
+    }
+
+    #[test]
+    fn negative_indices_count_from_the_end() {
+        assert_eq!(run("var l = [1, 2, 3]; print l[-1];").unwrap(), "3\n");
+        let err = run("var l = [1, 2, 3]; print l[-4];").unwrap_err();
+        assert_eq!(err, "List index -4 out of range for length 3");
+    }
+
+    #[test]
+    fn every_value_has_to_string() {
+        let output = run("
+            print (1.5).to_string() + \"!\"; print true.to_string();
+            print nil.to_string(); print \"s\".to_string();")
+        .unwrap();
+        assert_eq!(output, "1.5!\ntrue\nnil\ns\n");
+    }
+
+    #[test]
+    fn shifts_take_integers() {
+        assert_eq!(run("print 1 << 4; print 256 >> 2;").unwrap(), "16\n64\n");
+        let err = run("print 1.5 << 1;").unwrap_err();
+        assert_eq!(err, "Can only shift integers, got 1.5");
+        let err = run("print 1 << -1;").unwrap_err();
+        assert_eq!(err, "Shift count: expected a non-negative integer, got -1");
+    }
+
+    #[test]
+    fn property_and_index_errors_name_the_type() {
+        let err = run("var n = 1; print n.field;").unwrap_err();
+        assert_eq!(err, "number has no property 'field'");
+        let err = run("var b = true; print b[0];").unwrap_err();
+        assert_eq!(err, "Cannot index a boolean");
+    }
+
+    #[test]
+    fn unsupported_expressions_say_what_they_are() {
+        let (mut interpreter, _) = interpreter();
+        let this = Expr::This(SourceLocation { line: 3, col: 7 });
+        let err = interpreter.evaluate_expr(&this).unwrap_err();
+        assert_eq!(err.message, "not yet implemented: This");
+        assert_eq!((err.line, err.col), (3, 7));
+    }
+
+    #[test]
+    fn a_rest_parameter_collects_the_extra_arguments() {
+        let output = run("fun f(a, ...rest) { print rest; } f(1, 2, 3); f(1);").unwrap();
+        assert_eq!(output, "[2, 3]\n[]\n");
+    }
+
+    #[test]
+    fn or_skips_its_right_side_but_keeps_its_location() {
+        assert_eq!(run("print true or (1 / 0);").unwrap(), "true\n");
+        let err = runtime_error("print false or\n  (1 / 0);");
+        assert_eq!(err.message, "ZeroDivisionError: division by zero");
+        assert_eq!(err.line, 2);
+    }
+
+    #[test]
+    fn continue_in_a_for_loop_still_increments() {
+        let output = run("for (var i = 0; i < 5; i = i + 1) { if (i == 2) continue; print i; }");
+        assert_eq!(output.unwrap(), "0\n1\n3\n4\n");
+    }
+
+    #[test]
+    fn list_indices_must_be_whole_numbers_in_range() {
+        let index_error = |index: &str| {
+            run(&format!(
+                "var big = 1; for (var i = 0; i < 309; i = i + 1) big = big * 10; print [1][{}];",
+                index
+            ))
+            .unwrap_err()
+        };
+        assert_eq!(
+            index_error("0.5"),
+            "List index: expected a non-negative integer, got 0.5"
+        );
+        assert_eq!(
+            index_error("big"),
+            "List index: expected a non-negative integer, got inf"
+        );
+        assert_eq!(
+            index_error("big - big"),
+            "List index: expected a non-negative integer, got nan"
+        );
+        assert_eq!(
+            index_error("1000000000000000"),
+            "List index 1000000000000000 out of range for length 1"
+        );
+    }
+
+    #[test]
+    fn nil_arithmetic_is_an_error_unless_loose() {
+        let err = run("print nil + 1;").unwrap_err();
+        assert_eq!(
+            err,
+            "Invalid operands for binary operator Plus of types Nil and Number"
+        );
+        let (mut interpreter, output) = interpreter();
+        interpreter.loose_nil_arithmetic = true;
+        run_in(&mut interpreter, "print nil + 1; print \"a\" + nil;").unwrap();
+        assert_eq!(output.contents(), "1\na\n");
+    }
+
+    #[test]
+    fn string_methods_chain() {
+        assert_eq!(run("print \"  Hi  \".trim().upper();").unwrap(), "HI\n");
+    }
+
+    #[test]
+    fn nested_lists_compare_deeply() {
+        let output = run("
+            print [1, [2, 3]] == [1, [2, 3]];
+            print [1, [2, 3]] == [1, [2, 4]];
+            print [1] == [1, 1];")
+        .unwrap();
+        assert_eq!(output, "true\nfalse\nfalse\n");
+    }
+
+    #[test]
+    fn a_comprehension_builds_a_list() {
+        let output = run("print [x * 2 for (var x in [1, 2, 3])];").unwrap();
+        assert_eq!(output, "[2, 4, 6]\n");
+    }
+
+    #[test]
+    fn for_in_rejects_a_number() {
+        assert_eq!(run("for (x in [1, 2, 3]) print x;").unwrap(), "1\n2\n3\n");
+        let err = run("for (x in 5) print x;").unwrap_err();
+        assert_eq!(err, "Can only iterate over a list or a range, got number");
+    }
+
+    #[test]
+    fn functions_are_hoisted_within_their_scope() {
+        let is_even = "fun isEven(n) { if (n == 0) return true; return isOdd(n - 1); }";
+        let is_odd = "fun isOdd(n) { if (n == 0) return false; return isEven(n - 1); }";
+        for (first, second) in [(is_even, is_odd), (is_odd, is_even)] {
+            let source = format!(
+                "{{ print isEven(4); {} {} print isOdd(7); }}",
+                first, second
+            );
+            assert_eq!(run(&source).unwrap(), "true\ntrue\n");
+        }
+    }
+
+    #[test]
+    fn ranges_count_up_or_down_by_their_step() {
+        let output = run("
+            var up = []; for (i in range(0, 5)) up = concat(up, [i]);
+            var down = []; for (i in range(10, 0, -2)) down = concat(down, [i]);
+            print up; print down;")
+        .unwrap();
+        assert_eq!(output, "[0, 1, 2, 3, 4]\n[10, 8, 6, 4, 2]\n");
+    }
+
+    #[test]
+    fn an_assignment_is_worth_the_assigned_value() {
+        let output = run("var a; var b; print a = 1; a = b = 3; print [a, b];").unwrap();
+        assert_eq!(output, "1\n[3, 3]\n");
+    }
+
+    #[test]
+    fn nil_safe_access_skips_a_nil_receiver() {
+        let output = run("var n = nil; print n?.length; print \"abc\"?.length;").unwrap();
+        assert_eq!(output, "nil\n3\n");
+    }
+
+    #[test]
+    fn lists_destructure_into_names() {
+        let output = run("var (a, b) = [1, 2]; print a + b; (a, b) = [b, a]; print [a, b];");
+        assert_eq!(output.unwrap(), "3\n[2, 1]\n");
+        let err = run("var (a, b) = [1];").unwrap_err();
+        assert_eq!(
+            err,
+            "Can't destructure a list of 1 element(s) into 2 name(s)"
+        );
+    }
+
+    #[test]
+    fn trace_shows_each_statement_and_expression_value() {
+        let (mut interpreter, _) = interpreter();
+        let trace = SharedBuffer::default();
+        interpreter.error_output = Box::new(trace.clone());
+        interpreter.trace = true;
+        run_in(&mut interpreter, "var x = 1; x + 1;").unwrap();
+        assert_eq!(
+            trace.contents(),
+            "[trace] var x = 1;\n[trace] x + 1;\n[trace] => 2\n"
+        );
+    }
+
+    #[test]
+    fn restoring_a_snapshot_undoes_changes() {
+        let (mut interpreter, output) = interpreter();
+        let exec = |interpreter: &mut Interpreter, source: &str| {
+            let program = crate::parse(source.to_string()).unwrap();
+            interpreter.interpret(&program).unwrap();
+        };
+        exec(&mut interpreter, "var x = 1;");
+        let snapshot = interpreter.globals.borrow().snapshot();
+        exec(&mut interpreter, "x = 2; var y = 3;");
+        interpreter.globals.borrow_mut().restore(snapshot);
+        exec(&mut interpreter, "print x;");
+        assert_eq!(output.contents(), "1\n");
+        assert!(interpreter.globals.borrow().get(&symbol("y")).is_err());
+    }
+
+    #[test]
+    fn strings_repeat_a_whole_number_of_times() {
+        assert_eq!(
+            run("print \"x\" * 3; print 2 * \"ab\";").unwrap(),
+            "xxx\nabab\n"
+        );
+        let err = run("print \"x\" * -1;").unwrap_err();
+        assert_eq!(
+            err,
+            "String repetition count: expected a non-negative integer, got -1"
+        );
+    }
+
+    #[test]
+    fn expression_statements_discard_values_but_not_errors() {
+        assert_eq!(run("1 + 1; print \"ran\";").unwrap(), "ran\n");
+        let err = run("1 + nil; print \"not reached\";").unwrap_err();
+        assert_eq!(
+            err,
+            "Invalid operands for binary operator Plus of types Number and Nil"
+        );
+    }
+
+    #[test]
+    fn arguments_are_evaluated_left_to_right() {
+        let output = run("
+            var log = [];
+            fun note(x) { log = concat(log, [x]); return x; }
+            fun f(a, b, c) { return a - b - c; }
+            print f(note(1), note(2), note(3)); print log;")
+        .unwrap();
+        assert_eq!(output, "-4\n[1, 2, 3]\n");
+    }
+
+    #[test]
+    fn with_bindings_end_with_their_block() {
+        let early_return = "fun f() { with (var tmp = 2) { return tmp; } } print f();";
+        assert_eq!(run(early_return).unwrap(), "2\n");
+        for source in [
+            "with (var tmp = 2) { print tmp; } print tmp;",
+            &format!("{} print tmp;", early_return),
+        ] {
+            assert!(run(source).unwrap_err().contains("'tmp' escapes its scope"));
+            assert_eq!(
+                runtime_error(source).message,
+                "Use undefined variable 'tmp'"
+            );
         }
     }
+
+    #[test]
+    fn as_index_takes_only_non_negative_whole_numbers() {
+        assert_eq!(Value::Number(3.0).as_index(), Ok(3));
+        assert_eq!(
+            Value::Number(3.5).as_index(),
+            Err("expected a non-negative integer, got 3.5".to_string())
+        );
+        assert_eq!(
+            Value::Number(-1.0).as_index(),
+            Err("expected a non-negative integer, got -1".to_string())
+        );
+        assert_eq!(
+            Value::Nil.as_index(),
+            Err("expected a non-negative integer, got nil".to_string())
+        );
+    }
+
+    #[test]
+    fn rust_values_convert_both_ways() {
+        assert_eq!(f64::try_from(Value::from(2.5)), Ok(2.5));
+        assert_eq!(bool::try_from(Value::from(true)), Ok(true));
+        assert_eq!(
+            String::try_from(Value::from("hi".to_string())),
+            Ok("hi".to_string())
+        );
+        assert_eq!(String::try_from(Value::from("hi")), Ok("hi".to_string()));
+        assert_eq!(
+            f64::try_from(Value::from("hi")),
+            Err("expected a number, got string".to_string())
+        );
+    }
+
+    #[test]
+    fn a_runtime_error_can_carry_the_variables_in_scope() {
+        let source = "var g = 1; fun f(x) { var y = x * 2; return y / 0; } f(3);";
+        let program = crate::parse(source.to_string()).unwrap();
+        let (mut interpreter, _) = interpreter();
+        interpreter.dump_env_on_error = true;
+        let err = interpreter.interpret(&program).unwrap_err();
+        assert_eq!(
+            err.bindings.as_deref(),
+            Some("scope 1:\n  x = 3\n  y = 6\nglobals:\n  f = <fn f>\n  g = 1\n")
+        );
+        assert_eq!(runtime_error(source).bindings, None);
+    }
+
+    #[test]
+    fn a_top_level_block_keeps_its_declarations() {
+        let (mut interpreter, output) = interpreter();
+        run_in(&mut interpreter, "{ var x = 1; print x; }").unwrap();
+        assert_eq!(output.contents(), "1\n");
+        assert!(interpreter.global_names().iter().all(|name| name != "x"));
+    }
+
+    #[test]
+    fn a_failed_assert_shows_its_message_or_condition() {
+        assert_eq!(run("assert 1 == 1; print \"ok\";").unwrap(), "ok\n");
+        assert_eq!(
+            run("assert 1 == 2;").unwrap_err(),
+            "Assertion failed: 1 == 2"
+        );
+        assert_eq!(
+            run("assert 1 == 2, \"math is broken\";").unwrap_err(),
+            "Assertion failed: math is broken"
+        );
+    }
+
+    #[test]
+    fn empty_statements_do_nothing() {
+        assert_eq!(
+            run(";;; print 1;; ; if (true) ; print 2;").unwrap(),
+            "1\n2\n"
+        );
+        let program = crate::parse("print 1;;".to_string()).unwrap();
+        assert!(matches!(program[1], Stmt::Empty));
+        assert_eq!(ast_printer::print_stmt(&program[1]), ";");
+        let (mut interpreter, _) = interpreter();
+        interpreter.profile = Some(Profile::default());
+        run_in(&mut interpreter, ";;").unwrap();
+        assert_eq!(interpreter.profile.unwrap().count("Block"), 0);
+    }
+
+    #[test]
+    fn an_operator_error_spans_the_whole_expression() {
+        let source = "print nil + 1;";
+        let span = runtime_error(source).span.unwrap();
+        assert_eq!(&source[span.start..span.end], "nil + 1");
+    }
 }
@@ -0,0 +1,64 @@
+//! Runs the `lox` binary on small scripts, for the behavior that lives in
+//! `main.rs`: flags, exit codes and what goes to stdout and stderr.
+
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+/// Runs `lox` with `args`, feeding `source` to it as a script on stdin.
+fn lox(args: &[&str], source: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_rust-craft"))
+        .args(args)
+        .arg("/dev/stdin")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(source.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+#[test]
+fn max_errors_caps_the_report() {
+    let output = lox(&["--max-errors", "2"], "var = 1;\nvar = 2;\nvar = 3;\n");
+    assert!(!output.status.success());
+    let stderr = stderr(&output);
+    assert_eq!(stderr.matches("Expect a variable name").count(), 2);
+    assert!(stderr.contains("... and 1 more errors"));
+    assert!(stderr.contains("Error: aborting due to 3 previous error(s)"));
+}
+
+#[test]
+fn emit_tokens_json_dumps_the_token_stream() {
+    let output = lox(&["--emit-tokens-json"], "print 1;");
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.ends_with(
+        "[\n\
+         \x20 { \"type\": \"Print\", \"lexeme\": \"print\", \"line\": 1, \"col\": 1, \"start\": 0, \"end\": 5 },\n\
+         \x20 { \"type\": \"Number\", \"lexeme\": \"1\", \"line\": 1, \"col\": 7, \"start\": 6, \"end\": 7 },\n\
+         \x20 { \"type\": \"Semicolon\", \"lexeme\": \";\", \"line\": 1, \"col\": 8, \"start\": 7, \"end\": 8 },\n\
+         \x20 { \"type\": \"Eof\", \"lexeme\": \"\", \"line\": 1, \"col\": 9, \"start\": 8, \"end\": 8 }\n\
+         ]\n"
+    ));
+}
+
+#[test]
+fn scripts_recurse_past_the_library_default() {
+    let output = lox(
+        &[],
+        "fun f(n) { if (n == 0) return 0; return 1 + f(n - 1); } print f(5000);",
+    );
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.ends_with("5000\n"));
+}